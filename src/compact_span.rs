@@ -0,0 +1,220 @@
+/*!
+
+A `CompactSpan` is an 8-byte encoding of an `IdSpan` for data structures that hold very many
+spans, such as token and AST node vectors. A `Span` is 24 bytes and an `IdSpan` 16, while most
+spans are short and most programs have few sources. So, as in rustc, a `CompactSpan` stores the
+start, length, and source of such a span inline in a `u32` and two `u16`s, and any other span is
+interned in the `Sources` database, with the `CompactSpan` holding its index in the interner.
+
+Compact spans are created and expanded through the `Sources` database they belong to. Equal spans
+have equal compact spans, so compact spans from the same database can be compared and hashed
+directly. An interned compact span means nothing without its database, so with the
+`serialization` feature only inline compact spans can be serialized and deserialized. Serialize
+the `IdSpan` of a span that may be interned instead. (See `CompactSpan::id_span`.)
+
+```
+use saucepan::{CompactSpan, IdSpan, Sources};
+
+let mut sources = Sources::new();
+let id     = sources.add("main.x", "let x = 42;");
+let number = sources.get(id).unwrap().slice(8..10);
+
+let compact = number.compact(&sources).unwrap();
+assert_eq!(std::mem::size_of::<CompactSpan>(), 8);
+assert!(compact.is_inline());
+assert_eq!(compact.span(&sources).unwrap(), number);
+
+// Too long to store inline.
+let long = sources.compact(IdSpan::new(0usize, 100_000usize, id));
+assert!(!long.is_inline());
+assert_eq!(long.id_span(&sources).len(), 100_000);
+```
+
+*/
+
+use std::{
+  collections::HashMap,
+  fmt::{Debug, Formatter},
+  sync::Mutex,
+};
+
+#[cfg(feature = "serialization")]
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::{
+  ByteIndex,
+  IdSpan,
+  LocationError,
+  RawIndex,
+  SourceId,
+  SourceText,
+  Sources,
+  Span,
+};
+
+
+/// The value of `CompactSpan::length_or_tag` marking an interned span.
+const INTERNED: u16 = u16::MAX;
+
+
+/// A span packed into 8 bytes. Obtain one from `Span::compact` or `Sources::compact`. Serializing
+/// or deserializing an interned compact span is an error.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialization", derive(Deserialize), serde(try_from = "InlineSpan"))]
+pub struct CompactSpan {
+  /// The start of an inline span, or the index of an interned span in the interner.
+  start_or_index: u32,
+  /// The length of an inline span, or `INTERNED`.
+  length_or_tag : u16,
+  /// The source of an inline span.
+  source        : u16,
+}
+
+const _: () = assert!(std::mem::size_of::<CompactSpan>() == 8);
+
+/// The serialized form of a `CompactSpan`, which must be inline.
+#[cfg(feature = "serialization")]
+#[derive(Deserialize, Serialize)]
+#[serde(rename = "CompactSpan")]
+struct InlineSpan {
+  start_or_index: u32,
+  length_or_tag : u16,
+  source        : u16,
+}
+
+#[cfg(feature = "serialization")]
+impl TryFrom<InlineSpan> for CompactSpan {
+  type Error = &'static str;
+
+  fn try_from(span: InlineSpan) -> Result<Self, Self::Error> {
+    match span.length_or_tag {
+      INTERNED => Err("an interned CompactSpan cannot be deserialized"),
+      _ => Ok(
+        CompactSpan {
+          start_or_index: span.start_or_index,
+          length_or_tag : span.length_or_tag,
+          source        : span.source,
+        }
+      ),
+    }
+  }
+}
+
+#[cfg(feature = "serialization")]
+impl Serialize for CompactSpan {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    if !self.is_inline() {
+      return Err(serde::ser::Error::custom("an interned CompactSpan cannot be serialized"));
+    }
+    InlineSpan {
+      start_or_index: self.start_or_index,
+      length_or_tag : self.length_or_tag,
+      source        : self.source,
+    }.serialize(serializer)
+  }
+}
+
+impl CompactSpan {
+  /// Encodes `span` inline, or returns `None` if it is too long or its start or `SourceId` too
+  /// large and it must be interned instead.
+  pub fn inline(span: IdSpan) -> Option<CompactSpan> {
+    let length = u16::try_from(span.len()).ok().filter(|&length| length != INTERNED)?;
+    let source = u16::try_from(span.source_id().0).ok()?;
+    let start  = u32::try_from(usize::from(span.start())).ok()?;
+
+    Some(
+      CompactSpan {
+        start_or_index: start,
+        length_or_tag : length,
+        source,
+      }
+    )
+  }
+
+  /// The span interned at `index`.
+  pub(crate) fn interned(index: u32) -> CompactSpan {
+    CompactSpan {
+      start_or_index: index,
+      length_or_tag : INTERNED,
+      source        : 0,
+    }
+  }
+
+  /// Whether the span is stored inline rather than in the interner of a `Sources` database.
+  pub fn is_inline(self) -> bool {
+    self.length_or_tag != INTERNED
+  }
+
+  /// The `IdSpan` this compact span encodes. Panics if the span is interned in a different
+  /// `Sources` database than `sources`.
+  pub fn id_span<T: SourceText>(self, sources: &Sources<'_, T>) -> IdSpan {
+    match self.is_inline() {
+      true => IdSpan::new(
+        ByteIndex::new(self.start_or_index as usize),
+        self.length_or_tag as usize,
+        SourceId(self.source as RawIndex)
+      ),
+      false => sources.interned_span(self.start_or_index),
+    }
+  }
+
+  /// The `Span` this compact span encodes. (See `CompactSpan::id_span` and `Sources::span`.)
+  pub fn span<'s, 'n, T: SourceText>(self, sources: &'s Sources<'n, T>)
+    -> Result<Span<'n, 's, T>, LocationError<'n, 's, T>>
+  {
+    sources.span(self.id_span(sources))
+  }
+}
+
+
+/// The spans of a `Sources` database too large to be stored inline in a `CompactSpan`. Spans are
+/// interned through a shared reference, so a lexer can create compact spans while it borrows the
+/// text of the database.
+#[derive(Default)]
+pub(crate) struct SpanInterner {
+  inner: Mutex<Interned>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Interned {
+  spans  : Vec<IdSpan>,
+  indices: HashMap<IdSpan, u32>,
+}
+
+impl SpanInterner {
+  /// The index of `span` in the interner, interning it if it is not already.
+  pub(crate) fn intern(&self, span: IdSpan) -> u32 {
+    let mut interned = self.lock();
+    let Interned { spans, indices } = &mut *interned;
+    *indices.entry(span).or_insert_with(|| {
+      spans.push(span);
+      u32::try_from(spans.len() - 1).expect("too many interned spans")
+    })
+  }
+
+  /// The span interned at `index`. Panics if there is none.
+  pub(crate) fn get(&self, index: u32) -> IdSpan {
+    self.lock().spans[index as usize]
+  }
+
+  fn lock(&self) -> std::sync::MutexGuard<'_, Interned> {
+    // The interned spans are valid even if another thread panicked while holding the lock.
+    self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+  }
+}
+
+impl Clone for SpanInterner {
+  fn clone(&self) -> Self {
+    SpanInterner {
+      inner: Mutex::new(self.lock().clone())
+    }
+  }
+}
+
+impl Debug for SpanInterner {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("SpanInterner")
+     .field("len", &self.lock().spans.len())
+     .finish()
+  }
+}
@@ -0,0 +1,362 @@
+/*!
+
+A `Diagnostic` is a message for the user about their source code, e.g. an error or a warning,
+together with labels pointing at the spans it concerns. Its labels take `Span`s directly, so a
+diagnostic can be built from the spans a parser produces without converting them to byte ranges
+and file ids first. The design follows `codespan_reporting::diagnostic`.
+
+With the `reporting` feature, a diagnostic can be converted into a `codespan_reporting` diagnostic
+or emitted against the `Source` or `Sources` database its spans belong to in one call:
+
+```
+use saucepan::{Diagnostic, Label, Source};
+# #[cfg(feature = "reporting")]
+use codespan_reporting::term::{self, termcolor::NoColor};
+
+let source = Source::new("main.x", "let x = 1 + true;\n");
+let diagnostic =
+    Diagnostic::error()
+        .with_code("E0308")
+        .with_message("mismatched types")
+        .with_label(Label::primary(source.slice(12..16)).with_message("expected integer"))
+        .with_label(Label::secondary(source.slice(8..9)).with_message("because of this"))
+        .with_note("booleans are not numbers");
+
+# #[cfg(feature = "reporting")]
+# {
+let mut output = NoColor::new(Vec::new());
+diagnostic.emit(&mut output, &term::Config::default(), &source).unwrap();
+let output = String::from_utf8(output.into_inner()).unwrap();
+assert!(output.starts_with("error[E0308]: mismatched types"));
+# }
+```
+
+*/
+
+use std::fmt::{Debug, Display, Formatter};
+
+#[cfg(feature = "serialization")]
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "reporting")]
+use codespan_reporting::{
+  diagnostic as codespan,
+  files::Error as CodespanError,
+  term::{self, termcolor::WriteColor},
+};
+
+use crate::{
+  SourceText,
+  Span,
+  Suggestion,
+};
+#[cfg(feature = "reporting")]
+use crate::{
+  Source,
+  SourceId,
+  Sources,
+  TextSlice,
+};
+
+
+/// How serious a `Diagnostic` is. Severities are ordered from `Help`, the least severe, to `Bug`,
+/// the most severe.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub enum Severity {
+  Help,
+  Note,
+  Warning,
+  Error,
+  /// An unexpected bug in the tool reporting the diagnostic.
+  Bug,
+}
+
+impl Display for Severity {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Severity::Help => write!(f, "help"),
+      Severity::Note => write!(f, "note"),
+      Severity::Warning => write!(f, "warning"),
+      Severity::Error => write!(f, "error"),
+      Severity::Bug => write!(f, "bug"),
+    }
+  }
+}
+
+
+/// Whether a `Label` marks the cause of a diagnostic or gives additional context.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub enum LabelStyle {
+  Primary,
+  Secondary,
+}
+
+
+/// A span of source code and a message about it.
+pub struct Label<'n, 't, T: SourceText = &'t str> {
+  pub style  : LabelStyle,
+  pub span   : Span<'n, 't, T>,
+  /// The message to display with the span. It should not contain line breaks.
+  pub message: String,
+}
+
+impl<'n, 't, T: SourceText> Label<'n, 't, T> {
+  pub fn new(style: LabelStyle, span: Span<'n, 't, T>) -> Self {
+    Label {
+      style,
+      span,
+      message: String::new(),
+    }
+  }
+
+  /// A label marking the cause of a diagnostic.
+  pub fn primary(span: Span<'n, 't, T>) -> Self {
+    Label::new(LabelStyle::Primary, span)
+  }
+
+  /// A label giving additional context for a diagnostic.
+  pub fn secondary(span: Span<'n, 't, T>) -> Self {
+    Label::new(LabelStyle::Secondary, span)
+  }
+
+  pub fn with_message(mut self, message: impl Into<String>) -> Self {
+    self.message = message.into();
+    self
+  }
+}
+
+// `Clone` and `Debug` are implemented by hand so as not to require them of `T`.
+impl<'n, 't, T: SourceText> Clone for Label<'n, 't, T> {
+  fn clone(&self) -> Self {
+    Label {
+      style  : self.style,
+      span   : self.span,
+      message: self.message.clone(),
+    }
+  }
+}
+
+impl<'n, 't, T: SourceText> Debug for Label<'n, 't, T> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("Label")
+     .field("style", &self.style)
+     .field("span", &self.span)
+     .field("message", &self.message)
+     .finish()
+  }
+}
+
+
+/// A message for the user about their source code. Build one with the `with_*` methods, starting
+/// from `Diagnostic::error()`, `Diagnostic::warning()`, etc.
+pub struct Diagnostic<'n, 't, T: SourceText = &'t str> {
+  pub severity   : Severity,
+  /// An optional code identifying the kind of diagnostic, e.g. `E0308`.
+  pub code       : Option<String>,
+  /// The main message of the diagnostic. It should make sense on its own, without the labels and
+  /// notes, and should not contain line breaks.
+  pub message    : String,
+  pub labels     : Vec<Label<'n, 't, T>>,
+  /// Additional messages displayed after the labels. They may contain line breaks.
+  pub notes      : Vec<String>,
+  /// Proposed fixes, displayed after the notes.
+  pub suggestions: Vec<Suggestion<'n, 't, T>>,
+}
+
+impl<'n, 't, T: SourceText> Diagnostic<'n, 't, T> {
+  pub fn new(severity: Severity) -> Self {
+    Diagnostic {
+      severity,
+      code       : None,
+      message    : String::new(),
+      labels     : Vec::new(),
+      notes      : Vec::new(),
+      suggestions: Vec::new(),
+    }
+  }
+
+  pub fn bug() -> Self {
+    Diagnostic::new(Severity::Bug)
+  }
+
+  pub fn error() -> Self {
+    Diagnostic::new(Severity::Error)
+  }
+
+  pub fn warning() -> Self {
+    Diagnostic::new(Severity::Warning)
+  }
+
+  pub fn note() -> Self {
+    Diagnostic::new(Severity::Note)
+  }
+
+  pub fn help() -> Self {
+    Diagnostic::new(Severity::Help)
+  }
+
+  pub fn with_code(mut self, code: impl Into<String>) -> Self {
+    self.code = Some(code.into());
+    self
+  }
+
+  pub fn with_message(mut self, message: impl Into<String>) -> Self {
+    self.message = message.into();
+    self
+  }
+
+  pub fn with_label(mut self, label: Label<'n, 't, T>) -> Self {
+    self.labels.push(label);
+    self
+  }
+
+  pub fn with_labels(mut self, labels: impl IntoIterator<Item=Label<'n, 't, T>>) -> Self {
+    self.labels.extend(labels);
+    self
+  }
+
+  pub fn with_note(mut self, note: impl Into<String>) -> Self {
+    self.notes.push(note.into());
+    self
+  }
+
+  pub fn with_notes(mut self, notes: impl IntoIterator<Item=String>) -> Self {
+    self.notes.extend(notes);
+    self
+  }
+
+  pub fn with_suggestion(mut self, suggestion: Suggestion<'n, 't, T>) -> Self {
+    self.suggestions.push(suggestion);
+    self
+  }
+
+  pub fn with_suggestions(mut self, suggestions: impl IntoIterator<Item=Suggestion<'n, 't, T>>) -> Self {
+    self.suggestions.extend(suggestions);
+    self
+  }
+}
+
+#[cfg(feature = "reporting")]
+impl<'n, 't, T: SourceText> Diagnostic<'n, 't, T> {
+  /// Converts the diagnostic into a `codespan_reporting` diagnostic to be rendered against the
+  /// `Files` implementation of `source`. Gives `CodespanError::FileMissing` if a label's span
+  /// belongs to a different source.
+  pub fn to_codespan(&self, source: &Source<'n, T>) -> Result<codespan::Diagnostic<()>, CodespanError> {
+    self.to_codespan_with(|span| std::ptr::eq(span.source, source).then_some(()))
+  }
+
+  /// Converts the diagnostic into a `codespan_reporting` diagnostic to be rendered against the
+  /// `Files` implementation of `sources`. Gives `CodespanError::FileMissing` if a label's span
+  /// belongs to a source that is not in `sources`.
+  pub fn to_codespan_sources(&self, sources: &Sources<'n, T>)
+    -> Result<codespan::Diagnostic<SourceId>, CodespanError>
+  {
+    self.to_codespan_with(|span| {
+      let id = span.source_id()?;
+      std::ptr::eq(span.source, sources.get(id)?).then_some(id)
+    })
+  }
+
+  /// Renders the diagnostic to `writer`. The spans of its labels must belong to `source`.
+  pub fn emit(&self, writer: &mut dyn WriteColor, config: &term::Config, source: &Source<'n, T>)
+    -> Result<(), CodespanError>
+  {
+    term::emit(writer, config, source, &self.to_codespan(source)?)
+  }
+
+  /// Renders the diagnostic to `writer`. The spans of its labels must belong to sources in
+  /// `sources`.
+  pub fn emit_sources(&self, writer: &mut dyn WriteColor, config: &term::Config, sources: &Sources<'n, T>)
+    -> Result<(), CodespanError>
+  {
+    term::emit(writer, config, sources, &self.to_codespan_sources(sources)?)
+  }
+
+  fn to_codespan_with<F, FileId>(&self, file_id: F) -> Result<codespan::Diagnostic<FileId>, CodespanError>
+    where F: Fn(&Span<'n, 't, T>) -> Option<FileId>
+  {
+    let labels =
+        self.labels
+            .iter()
+            .map(|label| {
+              let style = match label.style {
+                LabelStyle::Primary => codespan::LabelStyle::Primary,
+                LabelStyle::Secondary => codespan::LabelStyle::Secondary,
+              };
+              let file_id = file_id(&label.span).ok_or(CodespanError::FileMissing)?;
+              Ok(codespan::Label::new(style, file_id, label.span).with_message(&label.message))
+            })
+            .collect::<Result<_, CodespanError>>()?;
+
+    Ok(
+      codespan::Diagnostic {
+        severity: self.severity.into(),
+        code    : self.code.clone(),
+        message : self.message.clone(),
+        labels,
+        // `codespan_reporting` has no notion of suggestions, so they become notes.
+        notes   : self.notes
+                      .iter()
+                      .cloned()
+                      .chain(self.suggestions.iter().map(suggestion_note))
+                      .collect(),
+      }
+    )
+  }
+}
+
+/// A note describing `suggestion` for `codespan_reporting`.
+#[cfg(feature = "reporting")]
+fn suggestion_note<T: SourceText>(suggestion: &Suggestion<'_, '_, T>) -> String {
+  let mut note = String::from("help");
+  if !suggestion.message.is_empty() {
+    note = format!("{}: {}", note, suggestion.message);
+  }
+  match suggestion.is_deletion() {
+    true => format!("{}: remove `{}`", note, suggestion.span.fragment().to_str_lossy()),
+    false => format!("{}: `{}`", note, suggestion.replacement),
+  }
+}
+
+// `Clone` and `Debug` are implemented by hand so as not to require them of `T`.
+impl<'n, 't, T: SourceText> Clone for Diagnostic<'n, 't, T> {
+  fn clone(&self) -> Self {
+    Diagnostic {
+      severity   : self.severity,
+      code       : self.code.clone(),
+      message    : self.message.clone(),
+      labels     : self.labels.clone(),
+      notes      : self.notes.clone(),
+      suggestions: self.suggestions.clone(),
+    }
+  }
+}
+
+impl<'n, 't, T: SourceText> Debug for Diagnostic<'n, 't, T> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("Diagnostic")
+     .field("severity", &self.severity)
+     .field("code", &self.code)
+     .field("message", &self.message)
+     .field("labels", &self.labels)
+     .field("notes", &self.notes)
+     .field("suggestions", &self.suggestions)
+     .finish()
+  }
+}
+
+
+#[cfg(feature = "reporting")]
+impl From<Severity> for codespan::Severity {
+  fn from(severity: Severity) -> Self {
+    match severity {
+      Severity::Help => codespan::Severity::Help,
+      Severity::Note => codespan::Severity::Note,
+      Severity::Warning => codespan::Severity::Warning,
+      Severity::Error => codespan::Severity::Error,
+      Severity::Bug => codespan::Severity::Bug,
+    }
+  }
+}
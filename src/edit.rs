@@ -0,0 +1,272 @@
+/*!
+
+Types describing edits to source text. An `Edit` records that a range of bytes of a text was
+replaced by some number of new bytes. It is returned by `Source::apply_edit` and can be used to
+carry `ByteIndex`es and `IdSpan`s into the old text over to the new text.
+
+A `Span` borrows its source, so it cannot outlive an edit to that source. Long-lived positions
+(cached diagnostics, symbol tables) should be stored as `IdSpan`s and rebased through a `SpanMap`,
+which collects the edits made to a source since the spans were recorded:
+
+```
+use saucepan::{IdSpan, Rebased, Source, SpanMap, SourceId};
+
+let mut source = Source::new("main.x", String::from("let x = 1;\nlet y = x;\n"));
+let id = SourceId(0);
+let x_use  = IdSpan::new(19usize, 1usize, id);
+let one    = IdSpan::new(8usize, 1usize, id);
+
+let mut map = SpanMap::new(id);
+map.push(source.apply_edit(0..0, "// header\n").unwrap());
+map.push(source.apply_edit(18..19, "42").unwrap());
+
+assert_eq!(map.rebase(x_use), Rebased::Moved(IdSpan::new(30usize, 1usize, id)));
+assert_eq!(map.rebase(one), Rebased::Deleted);
+```
+
+*/
+
+use std::ops::Range;
+
+#[cfg(feature = "serialization")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  ByteIndex,
+  ByteOffset,
+  IdSpan,
+  SourceId,
+};
+
+
+/// The replacement of the `old_length` bytes starting at `start` with `new_length` new bytes.
+///
+/// Positions in the old text are carried over to the new text by one rule: an index at the start
+/// of the replaced range stays in front of the replacement, and an index at its end moves behind
+/// it. For a pure insertion the start and end coincide, and the index moves behind the inserted
+/// text, so text inserted at a position pushes that position forward. A span's start follows this
+/// rule, while its end stays in front of text inserted exactly at it, so a span does not grow to
+/// cover text inserted right after it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub struct Edit {
+  pub start     : ByteIndex,
+  pub old_length: ByteOffset,
+  pub new_length: ByteOffset,
+}
+
+impl Edit {
+  /// An edit replacing the bytes in `range` with `new_length` bytes.
+  pub fn new<L: Into<ByteOffset>>(range: Range<ByteIndex>, new_length: L) -> Edit {
+    Edit {
+      start     : range.start,
+      old_length: range.end - range.start,
+      new_length: new_length.into(),
+    }
+  }
+
+  /// The end of the replaced range in the old text.
+  pub fn old_end(&self) -> ByteIndex {
+    self.start + self.old_length
+  }
+
+  /// The end of the replacement in the new text.
+  pub fn new_end(&self) -> ByteIndex {
+    self.start + self.new_length
+  }
+
+  /// The change in length of the text.
+  pub fn delta(&self) -> ByteOffset {
+    self.new_length - self.old_length
+  }
+
+  /// Carries `index` in the old text over to the new text. Indices before the replaced range are
+  /// unchanged, and indices at or after its end are shifted by `self.delta()`. An index at the
+  /// start of a non-empty replaced range is unchanged, while an index at a pure insertion moves
+  /// behind the inserted text. (See `Edit`.) Indices strictly inside the replaced range no longer
+  /// exist, so `None` is returned for them.
+  pub fn map_index(&self, index: ByteIndex) -> Option<ByteIndex> {
+    if index >= self.old_end() {
+      Some(index + self.delta())
+    } else if index <= self.start {
+      Some(index)
+    } else {
+      None
+    }
+  }
+
+  /// Carries `span` in the old text over to the new text. Spans entirely before or entirely after
+  /// the replaced range are kept (shifted as necessary). A span that overlaps the replaced range
+  /// is invalidated, and `None` is returned. Spans in sources other than the edited source must
+  /// not be given to this method. (See `Edit::rebase` for a finer grained result.)
+  pub fn map_span(&self, span: IdSpan) -> Option<IdSpan> {
+    match self.rebase(span) {
+      Rebased::Moved(span) => Some(span),
+      _ => None
+    }
+  }
+
+  /// Carries `span` in the old text over to the new text, reporting whether the text of the span
+  /// survived the edit. A pure insertion strictly inside a span grows the span. Spans in sources
+  /// other than the edited source must not be given to this method.
+  pub fn rebase(&self, span: IdSpan) -> Rebased {
+    let start   = span.start();
+    let end     = span.end();
+    let old_end = self.old_end();
+
+    if self.old_length.0 == 0 {
+      // A pure insertion overwrites nothing. (See `Edit` for the boundary rule.)
+      return match self.map_index(start) {
+        Some(new_start) if new_start != start => {
+          Rebased::Moved(IdSpan::new(new_start, span.len(), span.source_id()))
+        }
+        _ if end > self.start => {
+          let length = span.len() + self.new_length.to_usize();
+          Rebased::Moved(IdSpan::new(start, length, span.source_id()))
+        }
+        _ => Rebased::Moved(span),
+      };
+    }
+    // A span that merely touches the replaced range is not affected by it.
+    if end <= self.start {
+      return Rebased::Moved(span);
+    }
+    if start >= old_end {
+      return Rebased::Moved(IdSpan::new(start + self.delta(), span.len(), span.source_id()));
+    }
+    if start >= self.start && end <= old_end {
+      return Rebased::Deleted;
+    }
+
+    // The span and the replaced range overlap without the span being contained in it. If the
+    // span covers the whole replaced range, it now covers the replacement. Otherwise it is
+    // clipped to the part of its text outside of the replaced range.
+    let (new_start, new_end) =
+        if start <= self.start && end >= old_end {
+          (start, end + self.delta())
+        } else if start < self.start {
+          (start, self.start)
+        } else {
+          (self.new_end(), end + self.delta())
+        };
+
+    Rebased::PartiallyOverwritten(IdSpan::new(new_start, new_end - new_start, span.source_id()))
+  }
+}
+
+
+/// The result of carrying an `IdSpan` across one or more edits. (See `Edit::rebase` and
+/// `SpanMap::rebase`.)
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub enum Rebased {
+  /// The text of the span is untouched. The span may have moved.
+  Moved(IdSpan),
+  /// Part of the text of the span was replaced. The new span covers what remains of the original
+  /// text, together with the replacement if the span covered the whole replaced range.
+  PartiallyOverwritten(IdSpan),
+  /// All of the text of the span was replaced.
+  Deleted,
+}
+
+impl Rebased {
+  /// The rebased span, if any of its text survived.
+  pub fn span(self) -> Option<IdSpan> {
+    match self {
+      Rebased::Moved(span) | Rebased::PartiallyOverwritten(span) => Some(span),
+      Rebased::Deleted => None
+    }
+  }
+}
+
+
+/// A record of the edits made to a single source, in the order they were made, used to carry
+/// `ByteIndex`es and `IdSpan`s recorded before the edits over to the current text. Each edit is in
+/// the coordinates of the text produced by the edits before it, which is exactly what
+/// `Source::apply_edit` returns.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub struct SpanMap {
+  source_id: SourceId,
+  edits    : Vec<Edit>,
+}
+
+impl SpanMap {
+  /// An empty map for the source with id `source_id`.
+  pub fn new(source_id: SourceId) -> SpanMap {
+    SpanMap {
+      source_id,
+      edits: Vec::new()
+    }
+  }
+
+  pub fn source_id(&self) -> SourceId {
+    self.source_id
+  }
+
+  /// Records `edit` as having been applied after all edits already in the map.
+  pub fn push(&mut self, edit: Edit) {
+    self.edits.push(edit);
+  }
+
+  pub fn edits(&self) -> &[Edit] {
+    &self.edits
+  }
+
+  pub fn len(&self) -> usize {
+    self.edits.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.edits.is_empty()
+  }
+
+  /// Forgets all recorded edits, e.g. after all cached spans have been rebased.
+  pub fn clear(&mut self) {
+    self.edits.clear();
+  }
+
+  /// Carries `index` across every recorded edit, or returns `None` if an edit replaced the text
+  /// around it. (See `Edit::map_index`.)
+  pub fn map_index(&self, index: ByteIndex) -> Option<ByteIndex> {
+    self.edits.iter().try_fold(index, |index, edit| edit.map_index(index))
+  }
+
+  /// Carries `span` across every recorded edit. Spans into other sources are returned unchanged
+  /// as `Rebased::Moved`.
+  pub fn rebase(&self, span: IdSpan) -> Rebased {
+    if span.source_id() != self.source_id {
+      return Rebased::Moved(span);
+    }
+
+    let mut result = Rebased::Moved(span);
+    for edit in &self.edits {
+      result =
+          match result {
+            Rebased::Moved(span) => edit.rebase(span),
+            Rebased::PartiallyOverwritten(span) => {
+              match edit.rebase(span) {
+                Rebased::Moved(span) => Rebased::PartiallyOverwritten(span),
+                other => other
+              }
+            }
+            Rebased::Deleted => break,
+          };
+    }
+    result
+  }
+
+  /// Carries `span` across every recorded edit, returning `None` if any of its text was replaced.
+  pub fn map_span(&self, span: IdSpan) -> Option<IdSpan> {
+    match self.rebase(span) {
+      Rebased::Moved(span) => Some(span),
+      _ => None
+    }
+  }
+}
+
+impl Extend<Edit> for SpanMap {
+  fn extend<I: IntoIterator<Item=Edit>>(&mut self, edits: I) {
+    self.edits.extend(edits);
+  }
+}
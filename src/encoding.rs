@@ -0,0 +1,229 @@
+/*!
+
+Byte-order marks and the encodings source files can be decoded from. Source text is always UTF-8
+once it is in a `Source`. `Source::new` keeps its text byte for byte, but `Source::decode` strips
+the byte-order mark, so it does not shift the columns of the first line, and transcodes files in
+other encodings. The decoded `Source` remembers the byte-order mark and encoding, so the text
+can be written back out as it was read (`Source::encoded_bytes`), and it keeps an `OffsetMap` from
+byte indices into the text to byte offsets into the original file (`Source::original_offset`).
+
+```
+use saucepan::{ByteIndex, ByteOrderMark, Source, TextEncoding};
+
+// "hé" in UTF-16LE with a byte-order mark.
+let file   = [0xFF, 0xFE, b'h', 0, 0xE9, 0];
+let source = Source::decode("utf16.txt", &file, TextEncoding::Utf8).unwrap();
+
+assert_eq!(source.text(), "hé");
+assert_eq!(source.bom(), Some(ByteOrderMark::Utf16Le));
+assert_eq!(source.original_offset(ByteIndex(1)), 4);
+assert_eq!(source.encoded_bytes().unwrap(), file);
+```
+
+*/
+
+use std::{
+  fmt::{Display, Formatter},
+  io,
+};
+
+#[cfg(feature = "serialization")]
+use serde::{Deserialize, Serialize};
+
+use crate::ByteIndex;
+
+
+/// A byte-order mark at the start of a file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub enum ByteOrderMark {
+  Utf8,
+  Utf16Le,
+  Utf16Be,
+}
+
+impl ByteOrderMark {
+  /// The byte-order mark `bytes` starts with, if any.
+  pub fn detect(bytes: &[u8]) -> Option<ByteOrderMark> {
+    match bytes {
+      [0xEF, 0xBB, 0xBF, ..] => Some(ByteOrderMark::Utf8),
+      [0xFF, 0xFE, ..] => Some(ByteOrderMark::Utf16Le),
+      [0xFE, 0xFF, ..] => Some(ByteOrderMark::Utf16Be),
+      _ => None,
+    }
+  }
+
+  pub fn as_bytes(self) -> &'static [u8] {
+    match self {
+      ByteOrderMark::Utf8 => &[0xEF, 0xBB, 0xBF],
+      ByteOrderMark::Utf16Le => &[0xFF, 0xFE],
+      ByteOrderMark::Utf16Be => &[0xFE, 0xFF],
+    }
+  }
+
+  /// The encoding this byte-order mark indicates.
+  pub fn encoding(self) -> TextEncoding {
+    match self {
+      ByteOrderMark::Utf8 => TextEncoding::Utf8,
+      ByteOrderMark::Utf16Le => TextEncoding::Utf16Le,
+      ByteOrderMark::Utf16Be => TextEncoding::Utf16Be,
+    }
+  }
+}
+
+
+/// An encoding a source file can be decoded from. (See `Source::decode`.)
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub enum TextEncoding {
+  #[default]
+  Utf8,
+  Utf16Le,
+  Utf16Be,
+  /// ISO-8859-1, in which every byte is the code point of the same value.
+  Latin1,
+}
+
+impl TextEncoding {
+  /// Decodes `bytes`, which must not include a byte-order mark, into UTF-8, giving the decoded
+  /// text and the map from byte indices into the text to byte offsets into `bytes`. An error of
+  /// kind `InvalidData` is returned if `bytes` is not valid in this encoding.
+  pub fn decode(self, bytes: &[u8]) -> io::Result<(String, OffsetMap)> {
+    let mut text    = String::with_capacity(bytes.len());
+    let mut offsets = OffsetMap::default();
+
+    match self {
+
+      TextEncoding::Utf8 => {
+        text.push_str(std::str::from_utf8(bytes).map_err(invalid_data)?);
+      }
+
+      TextEncoding::Latin1 => {
+        for &byte in bytes {
+          offsets.push(text.len(), byte as char, 1);
+          text.push(byte as char);
+        }
+      }
+
+      TextEncoding::Utf16Le | TextEncoding::Utf16Be => {
+        if bytes.len() % 2 != 0 {
+          return Err(invalid_data("UTF-16 text has an odd number of bytes"));
+        }
+        let units = bytes.chunks_exact(2).map(|pair| {
+          let pair = [pair[0], pair[1]];
+          match self {
+            TextEncoding::Utf16Le => u16::from_le_bytes(pair),
+            _ => u16::from_be_bytes(pair),
+          }
+        });
+        for c in char::decode_utf16(units) {
+          let c = c.map_err(invalid_data)?;
+          offsets.push(text.len(), c, c.len_utf16() * 2);
+          text.push(c);
+        }
+      }
+
+    }
+
+    Ok((text, offsets))
+  }
+
+  /// Encodes `text` in this encoding. An error of kind `InvalidData` is returned if `text`
+  /// contains a character this encoding cannot represent.
+  pub fn encode(self, text: &str) -> io::Result<Vec<u8>> {
+    match self {
+      TextEncoding::Utf8 => Ok(text.as_bytes().to_vec()),
+      TextEncoding::Utf16Le => Ok(text.encode_utf16().flat_map(u16::to_le_bytes).collect()),
+      TextEncoding::Utf16Be => Ok(text.encode_utf16().flat_map(u16::to_be_bytes).collect()),
+      TextEncoding::Latin1 => {
+        text.chars()
+            .map(|c| u8::try_from(c).map_err(invalid_data))
+            .collect()
+      }
+    }
+  }
+}
+
+impl Display for TextEncoding {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      TextEncoding::Utf8 => write!(f, "UTF-8"),
+      TextEncoding::Utf16Le => write!(f, "UTF-16LE"),
+      TextEncoding::Utf16Be => write!(f, "UTF-16BE"),
+      TextEncoding::Latin1 => write!(f, "ISO-8859-1"),
+    }
+  }
+}
+
+pub(crate) fn invalid_data<E>(error: E) -> io::Error
+  where E: Into<Box<dyn std::error::Error + Send + Sync>>
+{
+  io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+
+/// A map from byte indices into decoded text to byte offsets into the original file. The text is
+/// stored as runs of characters that have the same length both in the text and in the file, so
+/// the map of an ASCII-only file in any encoding has at most one run.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub struct OffsetMap {
+  /// The length of the byte-order mark preceding the encoded text in the file.
+  prefix: usize,
+  /// The runs of the text, in order. If there are none, the text is the file itself.
+  runs  : Vec<Run>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+struct Run {
+  /// The byte index of the first character of the run in the text.
+  start         : usize,
+  /// The byte offset of the first character of the run in the file.
+  original_start: usize,
+  /// The length of every character of the run in the text.
+  width         : usize,
+  /// The length of every character of the run in the file.
+  original_width: usize,
+}
+
+impl OffsetMap {
+  /// The map for text that follows a byte-order mark of length `prefix` in the file.
+  pub(crate) fn with_prefix(mut self, prefix: usize) -> OffsetMap {
+    self.prefix = prefix;
+    self
+  }
+
+  /// Appends the character `c`, which starts at byte index `start` of the text and is
+  /// `original_width` bytes long in the file.
+  fn push(&mut self, start: usize, c: char, original_width: usize) {
+    let width = c.len_utf8();
+    match self.runs.last() {
+      Some(run) if run.width == width && run.original_width == original_width => {}
+      last => {
+        let original_start = last.map_or(0, |run| run.original_offset(start));
+        self.runs.push(Run { start, original_start, width, original_width });
+      }
+    }
+  }
+
+  /// The byte offset in the original file of the character at `index`. An index in the middle of
+  /// a character gives the offset of the start of the character.
+  pub fn original_offset(&self, index: ByteIndex) -> usize {
+    let index = usize::from(index);
+    let run   =
+        match self.runs.partition_point(|run| run.start <= index) {
+          0 => return self.prefix + index,
+          n => &self.runs[n - 1],
+        };
+
+    self.prefix + run.original_offset(index)
+  }
+}
+
+impl Run {
+  /// The offset in the file, not counting the byte-order mark, of the character at `index`.
+  fn original_offset(&self, index: usize) -> usize {
+    self.original_start + (index - self.start) / self.width * self.original_width
+  }
+}
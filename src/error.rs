@@ -8,16 +8,15 @@ parsing code will generate for the user.
 use std::{
   error,
   fmt::{Debug, Display, Formatter},
+  io,
+  path::PathBuf,
 };
 
 
 #[cfg(feature = "reporting")]
 use codespan_reporting::files::Error as CodespanError;
 
-use crate::{ByteIndex, LineIndex, Source, Span};
-
-
-type SourceID = usize;
+use crate::{ByteIndex, ColumnIndex, LineIndex, RawIndex, Source, SourceId, SourceText, Span};
 
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
@@ -39,10 +38,32 @@ impl Display for LineIndexOutOfBoundsError {
 }
 
 
+/// A `usize` was given that is too large for a `RawIndex`, e.g. the length of a text longer than
+/// 4GB without the `u64-indices` feature.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub struct IndexOverflowError {
+  pub given: usize,
+}
+
+impl error::Error for IndexOverflowError {}
+
+impl Display for IndexOverflowError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "Index too large - given: {}, max: {}",
+      self.given, RawIndex::MAX
+    )
+  }
+}
+
+
+/// A `SourceId` was given that does not belong to the `Sources` database. The `max` field is one
+/// past the largest valid `SourceId`.
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
 pub struct NotASourceError {
-  pub given: SourceID,
-  pub max: SourceID,
+  pub given: SourceId,
+  pub max: SourceId,
 }
 
 impl error::Error for NotASourceError {}
@@ -58,15 +79,107 @@ impl Display for NotASourceError {
 }
 
 
+/// An error encountered while adding a synthetic source. (See `Sources::add_expansion`.)
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
-pub enum LocationError<'n, 't> {
-  OutOfBounds { given: ByteIndex, source: &'t Source<'n, 't>},
+pub enum ExpansionError {
+  /// A span of the expansion refers to a source that is not in the database.
+  NotASource(NotASourceError),
+  /// The text is too long to be indexed by a `RawIndex`, or the database has no `SourceId` left.
+  IndexOverflow(IndexOverflowError),
+}
+
+impl error::Error for ExpansionError {
+  fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+    match self {
+      ExpansionError::NotASource(error) => Some(error),
+      ExpansionError::IndexOverflow(error) => Some(error),
+    }
+  }
+}
+
+impl Display for ExpansionError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ExpansionError::NotASource(error) => Display::fmt(error, f),
+      ExpansionError::IndexOverflow(error) => Display::fmt(error, f),
+    }
+  }
+}
+
+impl From<NotASourceError> for ExpansionError {
+  fn from(error: NotASourceError) -> Self {
+    ExpansionError::NotASource(error)
+  }
+}
+
+impl From<IndexOverflowError> for ExpansionError {
+  fn from(error: IndexOverflowError) -> Self {
+    ExpansionError::IndexOverflow(error)
+  }
+}
+
+
+/// An error encountered while loading sources from the filesystem. (See `Sources::load_file` and
+/// `Sources::load_dir`.) The variants depend on the enabled features, so the enum is non-exhaustive.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum LoadError {
+  /// Reading the file at `path` failed, or its contents are not valid for the source text type.
+  Io { path: PathBuf, error: io::Error },
+  /// The glob pattern given to `Sources::load_dir` is malformed.
+  #[cfg(feature = "load-dir")]
+  Pattern(glob::PatternError),
+}
+
+impl error::Error for LoadError {
+  fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+    match self {
+      LoadError::Io { error, .. } => Some(error),
+      #[cfg(feature = "load-dir")]
+      LoadError::Pattern(error) => Some(error),
+    }
+  }
+}
+
+impl Display for LoadError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      LoadError::Io { path, error } => {
+        write!(f, "Could not load source - path: {}, error: {}", path.display(), error)
+      }
+      #[cfg(feature = "load-dir")]
+      LoadError::Pattern(error) => write!(f, "Invalid glob pattern - {}", error),
+    }
+  }
+}
+
+#[cfg(feature = "load-dir")]
+impl From<glob::GlobError> for LoadError {
+  fn from(error: glob::GlobError) -> Self {
+    let path = error.path().to_path_buf();
+
+    LoadError::Io {
+      path,
+      error: error.into(),
+    }
+  }
+}
+
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub enum LocationError<'n, 't, T: SourceText = &'t str> {
+  OutOfBounds { given: ByteIndex, source: &'t Source<'n, T>},
   InvalidCharBoundary { given: ByteIndex },
+  NotASource { given: SourceId },
+  LineOutOfBounds { given: LineIndex, max: LineIndex },
+  ColumnOutOfBounds { given: ColumnIndex, max: ColumnIndex },
+  /// An edit would make the text longer than `RawIndex::MAX` bytes. `given` is the new length.
+  IndexOverflow { given: usize },
 }
 
-impl error::Error for LocationError<'_, '_> {}
+impl<T: SourceText> error::Error for LocationError<'_, '_, T> {}
 
-impl Display for LocationError<'_, '_>{
+impl<T: SourceText> Display for LocationError<'_, '_, T>{
   fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
     match self {
       &LocationError::OutOfBounds { given, source } => write!(
@@ -78,19 +191,43 @@ impl Display for LocationError<'_, '_>{
       LocationError::InvalidCharBoundary { given } => {
         write!(f, "Byte index within character boundary - given: {}", given)
       }
+      LocationError::NotASource { given } => {
+        write!(f, "Source ID not found - given: {}", given)
+      }
+      LocationError::LineOutOfBounds { given, max } => {
+        write!(f, "Line index out of bounds - given: {}, max: {}", given, max)
+      }
+      LocationError::ColumnOutOfBounds { given, max } => {
+        write!(f, "Column index out of bounds - given: {}, max: {}", given, max)
+      }
+      LocationError::IndexOverflow { given } => {
+        write!(f, "Index too large - given: {}, max: {}", given, RawIndex::MAX)
+      }
     }
   }
 }
 
+impl<T: SourceText> From<NotASourceError> for LocationError<'_, '_, T> {
+  fn from(error: NotASourceError) -> Self {
+    LocationError::NotASource { given: error.given }
+  }
+}
+
+impl<T: SourceText> From<LineIndexOutOfBoundsError> for LocationError<'_, '_, T> {
+  fn from(error: LineIndexOutOfBoundsError) -> Self {
+    LocationError::LineOutOfBounds { given: error.given, max: error.max }
+  }
+}
+
 #[cfg(feature = "reporting")]
-impl From<LocationError<'_, '_>> for CodespanError{
-    fn from(error: LocationError<'_, '_>) -> Self {
+impl<T: SourceText> From<LocationError<'_, '_, T>> for CodespanError{
+    fn from(error: LocationError<'_, '_, T>) -> Self {
         match error {
 
           LocationError::OutOfBounds{given, source} => {
             CodespanError::IndexTooLarge{
               given: given.into(),
-              max: source.last_line_index().into(),
+              max: source.len(),
             }
           },
 
@@ -100,26 +237,49 @@ impl From<LocationError<'_, '_>> for CodespanError{
             }
           }
 
+          LocationError::NotASource { .. } => CodespanError::FileMissing,
+
+          LocationError::LineOutOfBounds { given, max } => {
+            CodespanError::LineTooLarge {
+              given: given.into(),
+              max: max.into(),
+            }
+          }
+
+          LocationError::ColumnOutOfBounds { given, max } => {
+            CodespanError::ColumnTooLarge {
+              given: given.into(),
+              max: max.into(),
+            }
+          }
+
+          LocationError::IndexOverflow { given } => {
+            CodespanError::IndexTooLarge {
+              given,
+              max: RawIndex::MAX as usize,
+            }
+          }
+
         }
     }
 }
 
-// impl Debug for LocationError {
-//   fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-//     Display::fmt(self, f)
-//   }
-// }
+impl<T: SourceText> Debug for LocationError<'_, '_, T> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    Display::fmt(self, f)
+  }
+}
 
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
-pub struct SpanOutOfBoundsError<'n, 't> {
-  pub given: Span<'n, 't>,
-  pub span: Span<'n, 't>,
+pub struct SpanOutOfBoundsError<'n, 't, T: SourceText = &'t str> {
+  pub given: Span<'n, 't, T>,
+  pub span: Span<'n, 't, T>,
 }
 
-impl error::Error for SpanOutOfBoundsError<'_, '_> {}
+impl<T: SourceText> error::Error for SpanOutOfBoundsError<'_, '_, T> {}
 
-impl Display for SpanOutOfBoundsError<'_, '_> {
+impl<T: SourceText> Display for SpanOutOfBoundsError<'_, '_, T> {
   fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
     write!(
       f,
@@ -129,7 +289,7 @@ impl Display for SpanOutOfBoundsError<'_, '_> {
   }
 }
 
-impl Debug for SpanOutOfBoundsError<'_, '_> {
+impl<T: SourceText> Debug for SpanOutOfBoundsError<'_, '_, T> {
   fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
     Display::fmt(self, f)
   }
@@ -137,14 +297,14 @@ impl Debug for SpanOutOfBoundsError<'_, '_> {
 
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
-pub struct IncompatibleSourcesError<'n1, 't1, 'n2, 't2> {
-  pub lhs: Span<'n1, 't1>,
-  pub rhs: Span<'n2, 't2>,
+pub struct IncompatibleSourcesError<'n, 't, T: SourceText = &'t str> {
+  pub lhs: Span<'n, 't, T>,
+  pub rhs: Span<'n, 't, T>,
 }
 
-impl error::Error for IncompatibleSourcesError<'_, '_, '_, '_> {}
+impl<T: SourceText> error::Error for IncompatibleSourcesError<'_, '_, T> {}
 
-impl Display for IncompatibleSourcesError<'_, '_, '_, '_> {
+impl<T: SourceText> Display for IncompatibleSourcesError<'_, '_, T> {
   fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
     write!(
       f,
@@ -154,7 +314,49 @@ impl Display for IncompatibleSourcesError<'_, '_, '_, '_> {
   }
 }
 
-impl Debug for IncompatibleSourcesError<'_, '_, '_, '_>  {
+impl<T: SourceText> Debug for IncompatibleSourcesError<'_, '_, T>  {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    Display::fmt(self, f)
+  }
+}
+
+
+/// An error encountered while applying a batch of suggestions. (See `Source::apply_suggestions`.)
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SuggestionError<'n, 't, T: SourceText = &'t str> {
+  /// The span of a suggestion belongs to a different source.
+  WrongSource { given: Span<'n, 't, T> },
+  /// Two suggestions replace overlapping text, or insert different text at the same index.
+  Overlapping { first: Span<'n, 't, T>, second: Span<'n, 't, T> },
+  /// The span of a suggestion extends past the end of the text or, for string text, does not
+  /// start and end on `char` boundaries.
+  InvalidSpan { given: Span<'n, 't, T> },
+  /// The byte at index `given` of a byte source is kept by the suggestions but is not valid UTF-8.
+  InvalidUtf8 { given: ByteIndex },
+}
+
+impl<T: SourceText> error::Error for SuggestionError<'_, '_, T> {}
+
+impl<T: SourceText> Display for SuggestionError<'_, '_, T> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      SuggestionError::WrongSource { given } => {
+        write!(f, "Suggestion for a different source - given: {}", given)
+      }
+      SuggestionError::Overlapping { first, second } => {
+        write!(f, "Overlapping suggestions - first: {}, second: {}", first, second)
+      }
+      SuggestionError::InvalidSpan { given } => {
+        write!(f, "Suggestion span out of bounds or within a character - given: {}", given)
+      }
+      SuggestionError::InvalidUtf8 { given } => {
+        write!(f, "Invalid UTF-8 in the text kept by suggestions - given: {}", given)
+      }
+    }
+  }
+}
+
+impl<T: SourceText> Debug for SuggestionError<'_, '_, T> {
   fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
     Display::fmt(self, f)
   }
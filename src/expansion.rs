@@ -0,0 +1,88 @@
+/*!
+
+Provenance for text produced by macro expansion. Tokens produced by expanding a macro live in a
+synthetic source registered with `Sources::add_expansion`. The synthetic source remembers the
+`Expansion` that produced it: the span of the macro invocation (the call site) and the span of the
+macro's definition. Since the call site may itself lie in a synthetic source, expansions form a
+stack that can be walked outward from any span with `Span::expansions` or `IdSpan::expansions`,
+much like rustc's `ExpnData`.
+
+```
+use saucepan::{Expansion, Sources};
+
+let mut sources = Sources::new();
+let main  = sources.add("main.x", "macro twice(x) = x x;\nprint(twice(twice(1)));\n");
+let main_source = sources.get(main).unwrap();
+let definition  = main_source.slice(0..21).id_span().unwrap();
+let outer_call  = main_source.slice(28..43).id_span().unwrap();
+
+// The outer invocation expands to `twice(1) twice(1)`, which contains another invocation.
+let outer = sources.add_expansion(
+  "<twice>",
+  "twice(1) twice(1)",
+  Expansion { call_site: outer_call, definition }
+).unwrap();
+let inner_call = sources.get(outer).unwrap().slice(0..8).id_span().unwrap();
+let inner = sources.add_expansion(
+  "<twice>",
+  "1 1",
+  Expansion { call_site: inner_call, definition }
+).unwrap();
+
+let one = sources.get(inner).unwrap().slice(0..1);
+let call_sites: Vec<_> = one.expansions(&sources).map(|e| e.call_site).collect();
+
+assert_eq!(call_sites, vec![inner_call, outer_call]);
+assert_eq!(one.source_callsite(&sources), Some(outer_call));
+```
+
+*/
+
+#[cfg(feature = "serialization")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  IdSpan,
+  SourceText,
+  Sources,
+};
+
+
+/// The origin of a synthetic source produced by macro expansion.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub struct Expansion {
+  /// The span of the macro invocation that was expanded.
+  pub call_site : IdSpan,
+  /// The span of the definition of the macro that was invoked.
+  pub definition: IdSpan,
+}
+
+
+/// An iterator over the stack of expansions a span was produced by, innermost first. Created by
+/// `Span::expansions` and `IdSpan::expansions`.
+pub struct Expansions<'s, 'n, T: SourceText> {
+  sources: &'s Sources<'n, T>,
+  next   : Option<Expansion>,
+}
+
+impl<'s, 'n, T: SourceText> Expansions<'s, 'n, T> {
+  pub(crate) fn new(sources: &'s Sources<'n, T>, first: Option<Expansion>) -> Self {
+    Expansions {
+      sources,
+      next: first
+    }
+  }
+}
+
+impl<'s, 'n, T: SourceText> Iterator for Expansions<'s, 'n, T> {
+  type Item = Expansion;
+
+  fn next(&mut self) -> Option<Expansion> {
+    let current = self.next?;
+    // A synthetic source can only be created from call sites in sources that already exist, so
+    // the source ids strictly decrease and this walk always terminates.
+    self.next = self.sources.expansion(current.call_site.source_id());
+    Some(current)
+  }
+}
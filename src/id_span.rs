@@ -0,0 +1,102 @@
+/*!
+
+An `IdSpan` is a `Span` that refers to its source by `SourceId` rather than by reference. It does
+not borrow the `Sources` database, so it can be stored in long-lived data structures (ASTs,
+symbol tables, diagnostics) while more sources are added to the database. The text and location
+of an `IdSpan` are looked up through the `Sources` database it came from. (See `Sources::span`,
+`Sources::fragment`, and `Sources::location`.)
+
+*/
+
+use std::{
+  fmt::{Display, Formatter},
+  ops::Range,
+};
+
+#[cfg(feature = "serialization")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  ByteIndex,
+  ByteOffset,
+  Expansions,
+  SourceId,
+  SourceText,
+  Sources,
+};
+
+
+/// A span into the source with id `source_id`. Obtain one from `Span::id_span` or
+/// `Sources::source_span`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub struct IdSpan {
+  start    : ByteIndex,
+  length   : ByteOffset,
+  source_id: SourceId,
+}
+
+impl IdSpan {
+  pub fn new<S: Into<ByteIndex>, L: Into<ByteOffset>>(
+    start    : S,
+    length   : L,
+    source_id: SourceId
+  ) -> IdSpan
+  {
+    IdSpan {
+      start : start.into(),
+      length: length.into(),
+      source_id
+    }
+  }
+
+  pub fn source_id(self) -> SourceId {
+    self.source_id
+  }
+
+  pub fn start(self) -> ByteIndex {
+    self.start
+  }
+
+  pub fn end(self) -> ByteIndex {
+    self.start + self.length
+  }
+
+  pub fn len(self) -> usize {
+    self.length.into()
+  }
+
+  pub fn is_empty(self) -> bool {
+    self.len() == 0
+  }
+
+  /// Walks outward through the stack of macro expansions that produced this span, innermost
+  /// first. (See `Span::expansions`.)
+  pub fn expansions<'s, 'n, T: SourceText>(self, sources: &'s Sources<'n, T>)
+    -> Expansions<'s, 'n, T>
+  {
+    sources.expansions(self.source_id)
+  }
+
+  /// The call site of the outermost macro invocation this span was produced by, or the span
+  /// itself if it is not the product of macro expansion. (See `Span::source_callsite`.)
+  pub fn source_callsite<T: SourceText>(self, sources: &Sources<'_, T>) -> IdSpan {
+    match self.expansions(sources).last() {
+      Some(expansion) => expansion.call_site,
+      None => self,
+    }
+  }
+}
+
+
+impl Display for IdSpan {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    write!(f, "IdSpan<{}:{}..{}>", self.source_id, self.start, self.end())
+  }
+}
+
+impl From<IdSpan> for Range<usize> {
+  fn from(span: IdSpan) -> Range<usize> {
+    span.start.into()..span.end().into()
+  }
+}
@@ -6,13 +6,31 @@ use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
 #[cfg(feature = "serialization")]
 use serde::{Deserialize, Serialize};
 
+use crate::IndexOverflowError;
+
 
 /// We use a 32-bit integer here for space efficiency, assuming we won't be working with sources
-/// larger than 4GB.
+/// larger than 4GB. Enable the `u64-indices` feature for larger sources.
+#[cfg(not(feature = "u64-indices"))]
 pub type RawIndex = u32;
+#[cfg(feature = "u64-indices")]
+pub type RawIndex = u64;
 pub type RawOffset = i64;
 
 
+/// Converts `n` to a `RawIndex`, or gives an error if it is too large.
+#[inline(always)]
+pub(crate) fn try_raw_index(n: usize) -> Result<RawIndex, IndexOverflowError> {
+  RawIndex::try_from(n).map_err(|_| IndexOverflowError { given: n })
+}
+
+/// Converts `n` to a `RawIndex`, panicking instead of truncating if it is too large.
+#[inline(always)]
+fn raw_index(n: usize) -> RawIndex {
+  try_raw_index(n).unwrap_or_else(|error| panic!("{}", error))
+}
+
+
 #[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct LineIndex(pub RawIndex);
@@ -104,7 +122,7 @@ macro_rules! impl_number {
     impl From<usize> for $Number{
       #[inline(always)]
       fn from(n: usize) -> $Number {
-          $Number(n as RawIndex)
+          $Number(raw_index(n))
       }
     }
   }
@@ -115,9 +133,16 @@ macro_rules! impl_index {
   ($Index:ident, $Offset:ident) => {
 
     impl $Index {
+      /// Panics if `n` is larger than `RawIndex::MAX`. (See `Self::try_new`.)
       #[inline(always)]
       pub fn new(n: usize) -> Self {
-        $Index(n as RawIndex)
+        $Index(raw_index(n))
+      }
+
+      /// Gives an error instead of panicking if `n` is larger than `RawIndex::MAX`.
+      #[inline(always)]
+      pub fn try_new(n: usize) -> Result<Self, IndexOverflowError> {
+        try_raw_index(n).map($Index)
       }
     }
 
@@ -200,10 +225,11 @@ macro_rules! impl_index {
       }
     }
 
+    /// Panics if `i` is larger than `RawIndex::MAX`. (See `Self::try_new`.)
     impl From<usize> for $Index {
         #[inline]
         fn from(i: usize) -> Self {
-            $Index(i as RawIndex)
+            $Index::new(i)
         }
     }
 
@@ -319,7 +345,7 @@ macro_rules! impl_index {
 
         #[inline]
         fn sub(self, rhs: $Offset) -> $Index {
-            $Index((self.0 as RawOffset - rhs.0 as RawOffset) as u32)
+            $Index((self.0 as RawOffset - rhs.0 as RawOffset) as RawIndex)
         }
     }
 
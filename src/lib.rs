@@ -1,4 +1,3 @@
-#![feature(layout_for_ptr)]
 //! Utilities for working with source code and printing nicely formatted
 //! diagnostic information like warnings and errors.
 // #![feature(const_fn)]
@@ -25,24 +24,47 @@ pub use shims::{
 };
 
 
+mod text;
 mod source;
 mod sources;
 mod index_types;
 mod location;
+mod encoding;
+mod line_ending;
 mod error;
 mod span;
+mod id_span;
+mod compact_span;
+mod expansion;
+mod edit;
+mod span_tree;
+mod diagnostic;
+mod suggestion;
+mod render;
+#[cfg(feature = "serialization")]
+mod report;
 #[cfg(test)]
 mod tests;
 
 
 pub use crate::{
   error::{
+    ExpansionError,
+    IndexOverflowError,
     LineIndexOutOfBoundsError,
+    IncompatibleSourcesError,
+    LoadError,
     LocationError,
-    SpanOutOfBoundsError
+    NotASourceError,
+    SpanOutOfBoundsError,
+    SuggestionError
   },
   source::Source,
-  sources::Sources,
+  sources::{
+    Include,
+    SourceId,
+    Sources
+  },
   index_types::{
     ColumnIndex,
     ByteIndex,
@@ -57,6 +79,79 @@ pub use crate::{
     RawIndex,
     RawOffset
   },
-  location::Location,
-  span::Span
+  encoding::{
+    ByteOrderMark,
+    OffsetMap,
+    TextEncoding
+  },
+  line_ending::{
+    LineEnding,
+    LineTerminators
+  },
+  location::{
+    Location,
+    PositionEncoding
+  },
+  span::{
+    Difference,
+    LinePiece,
+    LinePieces,
+    Lines,
+    Span
+  },
+  id_span::IdSpan,
+  compact_span::CompactSpan,
+  edit::{
+    Edit,
+    Rebased,
+    SpanMap,
+  },
+  span_tree::{
+    SpanTree,
+    SpanTreeQuery,
+  },
+  diagnostic::{
+    Diagnostic,
+    Label,
+    LabelStyle,
+    Severity,
+  },
+  suggestion::{
+    Applicability,
+    Suggestion,
+  },
+  render::RenderConfig,
+  expansion::{
+    Expansion,
+    Expansions
+  },
+  text::{
+    EditableText,
+    FromFileContents,
+    SourceText,
+    TextSlice
+  }
+};
+#[cfg(feature = "serialization")]
+pub use crate::report::{
+  JsonCode,
+  JsonDiagnostic,
+  JsonExpansion,
+  JsonSpan,
+  JsonSpanLine,
+  SarifArtifactChange,
+  SarifArtifactContent,
+  SarifArtifactLocation,
+  SarifDriver,
+  SarifFix,
+  SarifLocation,
+  SarifLog,
+  SarifMessage,
+  SarifPhysicalLocation,
+  SarifRegion,
+  SarifReplacement,
+  SarifResult,
+  SarifRule,
+  SarifRun,
+  SarifTool,
 };
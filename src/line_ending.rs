@@ -0,0 +1,159 @@
+/*!
+
+Line terminators and line-ending styles. By default only `\n` ends a line, so the `\r` of a CRLF
+line ending is part of the line's content, and a file using old Mac style lone `\r` line endings
+is a single line. `LineTerminators::All` additionally treats a lone `\r`, U+2028 LINE SEPARATOR,
+and U+2029 PARAGRAPH SEPARATOR as line terminators. (See `Source::set_line_terminators`.)
+
+Independent of which terminators split lines, every `Source` records the line-ending style of its
+text, which tools that write the text back out can use to preserve it. (See
+`Source::line_ending`.)
+
+*/
+
+use std::ops::Range;
+
+#[cfg(feature = "serialization")]
+use serde::{Deserialize, Serialize};
+
+use memchr::{Memchr, Memchr2, Memchr3};
+
+
+/// The byte sequences that end a line.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub enum LineTerminators {
+  /// Only `\n` ends a line. A CRLF line ending is recognized as a terminator by
+  /// `Source::line_span_without_terminator`, but a lone `\r` is not.
+  #[default]
+  Lf,
+  /// `\n`, `\r\n`, a lone `\r`, U+2028, and U+2029 each end a line.
+  All,
+}
+
+impl LineTerminators {
+  /// The byte index following each line terminator in `text[from..]`, offset by `from`. The
+  /// index `from` must not be in the middle of a terminator.
+  pub(crate) fn line_starts(self, text: &[u8], from: usize)
+    -> Box<dyn '_ + Iterator<Item=usize>>
+  {
+    match self {
+
+      LineTerminators::Lf => Box::new(Memchr::new(b'\n', &text[from..]).map(move |i| from + i + 1)),
+
+      LineTerminators::All => Box::new(
+        Memchr3::new(b'\n', b'\r', 0xE2, &text[from..])
+          .map(move |i| from + i)
+          .filter_map(move |i| {
+            match text[i] {
+              b'\n' => Some(i + 1),
+              // The line starts after the `\n` of a CRLF.
+              b'\r' if text.get(i + 1) == Some(&b'\n') => None,
+              b'\r' => Some(i + 1),
+              // U+2028 and U+2029 are encoded as E2 80 A8 and E2 80 A9.
+              _ if matches!(text.get(i + 1..i + 3), Some([0x80, 0xA8 | 0xA9])) => Some(i + 3),
+              _ => None,
+            }
+          })
+      ),
+
+    }
+  }
+
+  /// The length of the terminator at the end of `line`, or zero if `line` does not end with one.
+  pub(crate) fn terminator_length(self, line: &[u8]) -> usize {
+    if line.ends_with(b"\r\n") {
+      2
+    } else if line.ends_with(b"\n") {
+      1
+    } else if self == LineTerminators::Lf {
+      0
+    } else if line.ends_with(b"\r") {
+      1
+    } else if line.ends_with("\u{2028}".as_bytes()) || line.ends_with("\u{2029}".as_bytes()) {
+      3
+    } else {
+      0
+    }
+  }
+}
+
+
+/// The line-ending style of a text.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub enum LineEnding {
+  /// `\n`, as on Unix.
+  Lf,
+  /// `\r\n`, as on Windows.
+  CrLf,
+  /// A lone `\r`, as on classic Mac OS.
+  Cr,
+  /// More than one of the above.
+  Mixed,
+}
+
+impl LineEnding {
+  pub fn as_str(self) -> Option<&'static str> {
+    match self {
+      LineEnding::Lf => Some("\n"),
+      LineEnding::CrLf => Some("\r\n"),
+      LineEnding::Cr => Some("\r"),
+      LineEnding::Mixed => None,
+    }
+  }
+}
+
+
+/// The number of line endings of each style in a text. Kept up to date by `Source::apply_edit`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub(crate) struct LineEndingCounts {
+  lf  : usize,
+  crlf: usize,
+  cr  : usize,
+}
+
+impl LineEndingCounts {
+  /// Counts the line endings starting in `text[range]`. A `\r` at the end of the range is
+  /// classified by the byte following it, and `range.start` must not be the `\n` of a CRLF.
+  pub(crate) fn scan(text: &[u8], range: Range<usize>) -> LineEndingCounts {
+    let mut counts    = LineEndingCounts::default();
+    let mut skip_next = false;
+
+    for i in Memchr2::new(b'\n', b'\r', &text[range.clone()]).map(|i| range.start + i) {
+      if std::mem::take(&mut skip_next) {
+        continue;
+      }
+      if text[i] == b'\n' {
+        counts.lf += 1;
+      } else if text.get(i + 1) == Some(&b'\n') {
+        counts.crlf += 1;
+        skip_next = true;
+      } else {
+        counts.cr += 1;
+      }
+    }
+
+    counts
+  }
+
+  /// Replaces the counts of `old`, a part of the text that was edited, with those of `new`,
+  /// what that part became.
+  pub(crate) fn replace(&mut self, old: LineEndingCounts, new: LineEndingCounts) {
+    self.lf   = self.lf - old.lf + new.lf;
+    self.crlf = self.crlf - old.crlf + new.crlf;
+    self.cr   = self.cr - old.cr + new.cr;
+  }
+
+  /// The line-ending style of the text, or `None` if it has no line endings.
+  pub(crate) fn line_ending(&self) -> Option<LineEnding> {
+    match (self.lf > 0, self.crlf > 0, self.cr > 0) {
+      (false, false, false) => None,
+      (true, false, false) => Some(LineEnding::Lf),
+      (false, true, false) => Some(LineEnding::CrLf),
+      (false, false, true) => Some(LineEnding::Cr),
+      _ => Some(LineEnding::Mixed),
+    }
+  }
+}
@@ -4,6 +4,11 @@
   typically only used for reporting. Note they are indices, not "numbers", and thus are zero-based
   instead of one-based.
 
+  What a column counts is determined by a `PositionEncoding`. Compilers usually count characters,
+  while the Language Server Protocol counts UTF-16 code units by default. With the
+  `unicode-columns` feature, columns can also count grapheme clusters or the cells a terminal
+  uses to display the text, which is what a caret under the text must line up with.
+
 */
 
 
@@ -12,6 +17,14 @@ use std::fmt::Display;
 #[cfg(feature = "serialization")]
 use serde::{Deserialize, Serialize};
 
+use std::ops::Range;
+
+use bytecount::num_chars;
+#[cfg(feature = "unicode-columns")]
+use unicode_segmentation::UnicodeSegmentation;
+#[cfg(feature = "unicode-columns")]
+use unicode_width::UnicodeWidthStr;
+
 use crate::{ColumnIndex, ColumnNumber, LineIndex, LineNumber};
 
 /// A location, a (line, column) pair, in a source file.
@@ -53,3 +66,142 @@ impl Display for Location {
   }
 }
 
+
+
+/// The unit in which the column of a `Location` is measured. The names follow the
+/// `PositionEncodingKind` of the Language Server Protocol. The `unicode-columns` feature adds
+/// variants, so the enum is non-exhaustive.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+#[non_exhaustive]
+pub enum PositionEncoding {
+  /// Columns count bytes.
+  Utf8,
+  /// Columns count UTF-16 code units. Characters outside of the Basic Multilingual Plane count
+  /// twice. This is the default encoding of the Language Server Protocol.
+  Utf16,
+  /// Columns count characters (Unicode scalar values).
+  #[default]
+  Utf32,
+  /// Columns count characters, except that a tab advances to the next multiple of `tab_width`,
+  /// as in an editor. (See `Source::visual_column`.)
+  Visual { tab_width: u32 },
+  /// Columns count extended grapheme clusters, i.e. user-perceived characters.
+  #[cfg(feature = "unicode-columns")]
+  Graphemes,
+  /// Columns count terminal cells: East Asian wide characters and most emoji take two cells,
+  /// combining marks take none, and a tab advances to the next multiple of `tab_width`.
+  #[cfg(feature = "unicode-columns")]
+  DisplayWidth { tab_width: u32 },
+}
+
+impl PositionEncoding {
+  /// The number of columns spanned by the UTF-8 encoded `text`, which must start at the start of
+  /// a line for tab stops to be placed correctly.
+  pub fn columns(self, text: &[u8]) -> usize {
+    match self {
+      PositionEncoding::Utf8 => text.len(),
+      // The fast paths only agree with `self.units(..)`, which gives every invalid byte its own
+      // column, on valid UTF-8.
+      _ if std::str::from_utf8(text).is_err() => self.units(text).last().map_or(0, |unit| unit.column),
+      // Exactly the characters encoded with four bytes need a surrogate pair.
+      PositionEncoding::Utf16 => num_chars(text) + text.iter().filter(|&&b| b >= 0xF0).count(),
+      PositionEncoding::Utf32 => num_chars(text),
+      _ => self.units(text).last().map_or(0, |unit| unit.column),
+    }
+  }
+
+  /// Splits the line `text` into the units this encoding counts, each with the column it ends
+  /// at. Bytes that are not valid UTF-8 are units by themselves and span one column.
+  pub(crate) fn units(self, text: &[u8]) -> impl '_ + Iterator<Item=Unit> {
+    let mut pieces = Vec::new();
+    let mut start  = 0;
+    while start < text.len() {
+      let rest = &text[start..];
+      let (valid, invalid_length) =
+        match std::str::from_utf8(rest) {
+          Ok(valid)  => (valid, 0),
+          Err(error) => {
+            let valid = std::str::from_utf8(&rest[..error.valid_up_to()]).unwrap();
+            // A sequence cut off by the end of the text is invalid as a whole.
+            (valid, error.error_len().unwrap_or(rest.len() - error.valid_up_to()))
+          }
+        };
+
+      pieces.extend(
+        self.segment(valid)
+            .map(|(offset, piece)| (start + offset .. start + offset + piece.len(), piece))
+      );
+      let invalid_start = start + valid.len();
+      pieces.extend((invalid_start..invalid_start + invalid_length).map(|i| (i..i + 1, "\u{FFFD}")));
+      start = invalid_start + invalid_length;
+    }
+
+    let mut column = 0;
+    pieces.into_iter()
+          .map(move |(bytes, piece)| {
+            column = self.advance(column, &bytes, piece);
+            Unit { bytes, column }
+          })
+  }
+
+  /// Splits valid UTF-8 text into `char`s or grapheme clusters with their byte offsets.
+  fn segment(self, text: &str) -> Box<dyn '_ + Iterator<Item=(usize, &str)>> {
+    match self {
+      #[cfg(feature = "unicode-columns")]
+      PositionEncoding::Graphemes | PositionEncoding::DisplayWidth { .. } => {
+        Box::new(text.grapheme_indices(true))
+      }
+      _ => Box::new(text.char_indices().map(move |(i, c)| (i, &text[i..i + c.len_utf8()]))),
+    }
+  }
+
+  /// The column following `piece`, which occupies the bytes `bytes` and starts at `column`.
+  fn advance(self, column: usize, bytes: &Range<usize>, piece: &str) -> usize {
+    match self {
+      PositionEncoding::Utf8 => column + bytes.len(),
+      PositionEncoding::Utf16 => column + piece.chars().map(char::len_utf16).sum::<usize>(),
+      PositionEncoding::Utf32 => column + 1,
+      PositionEncoding::Visual { tab_width } => {
+        if piece == "\t" { next_tab_stop(column, tab_width) } else { column + 1 }
+      }
+      #[cfg(feature = "unicode-columns")]
+      PositionEncoding::Graphemes => column + 1,
+      #[cfg(feature = "unicode-columns")]
+      PositionEncoding::DisplayWidth { tab_width } => {
+        if piece == "\t" { next_tab_stop(column, tab_width) } else { column + piece.width() }
+      }
+    }
+  }
+}
+
+/// The first multiple of `tab_width` greater than `column`. A tab width of zero is treated as one.
+fn next_tab_stop(column: usize, tab_width: u32) -> usize {
+  let tab_width = tab_width.max(1) as usize;
+  (column / tab_width + 1) * tab_width
+}
+
+
+/// A `char` or grapheme cluster occupying `bytes` of a line and ending at column `column`. (See
+/// `PositionEncoding::units`.)
+pub(crate) struct Unit {
+  pub bytes : Range<usize>,
+  pub column: usize,
+}
+
+impl Display for PositionEncoding {
+  /// Writes the LSP name of the encoding. The encodings LSP does not know are given descriptive
+  /// names.
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      PositionEncoding::Utf8 => write!(f, "utf-8"),
+      PositionEncoding::Utf16 => write!(f, "utf-16"),
+      PositionEncoding::Utf32 => write!(f, "utf-32"),
+      PositionEncoding::Visual { tab_width } => write!(f, "visual(tab={})", tab_width),
+      #[cfg(feature = "unicode-columns")]
+      PositionEncoding::Graphemes => write!(f, "graphemes"),
+      #[cfg(feature = "unicode-columns")]
+      PositionEncoding::DisplayWidth { tab_width } => write!(f, "display-width(tab={})", tab_width),
+    }
+  }
+}
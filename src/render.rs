@@ -0,0 +1,535 @@
+/*!
+
+A renderer for `Diagnostic`s that does not need `codespan-reporting`, so builds without the
+`reporting` feature can still show readable diagnostics. The output follows the familiar layout of
+rustc and codespan: a header, then for each source the lines the labels touch, with a line number
+gutter, underlines beneath single-line labels, and connecting lines drawn in the gutter for labels
+spanning several lines. Notes follow, and then each suggestion as a `help` section showing the
+lines it changes. ANSI colors are optional. (See `RenderConfig`.)
+
+The location in the `-->` line is given in the source's own `PositionEncoding`, while underlines
+are placed by display width with tabs expanded to the source's tab stops, so they line up with
+the text in a terminal.
+
+```
+use saucepan::{Diagnostic, Label, RenderConfig, Source};
+
+let source = Source::new("main.x", "let x = 1 + true;\n");
+let diagnostic =
+    Diagnostic::error()
+        .with_message("mismatched types")
+        .with_label(Label::primary(source.slice(12..16)).with_message("expected integer"));
+
+assert_eq!(
+  diagnostic.render(&RenderConfig::default()),
+  concat!(
+    "error: mismatched types\n",
+    " --> main.x:1:13\n",
+    "  |\n",
+    "1 | let x = 1 + true;\n",
+    "  |             ^^^^ expected integer\n",
+  )
+);
+```
+
+*/
+
+use std::{
+  cmp::Reverse,
+  fmt::{self, Display, Formatter, Write},
+};
+
+use crate::{
+  AsBytes,
+  ByteIndex,
+  Diagnostic,
+  LabelStyle,
+  LineIndex,
+  PositionEncoding,
+  Severity,
+  Source,
+  SourceText,
+  Span,
+  Suggestion,
+  TextSlice,
+};
+use crate::location::Unit;
+
+
+/// Options for `Diagnostic::render`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct RenderConfig {
+  /// Whether to color the output with ANSI escape codes.
+  pub color: bool,
+}
+
+impl RenderConfig {
+  /// The configuration for colored output.
+  pub fn colored() -> RenderConfig {
+    RenderConfig { color: true }
+  }
+}
+
+
+impl<'n, 't, T: SourceText> Diagnostic<'n, 't, T> {
+  /// Renders the diagnostic as text. (See the `render` module.)
+  pub fn render(&self, config: &RenderConfig) -> String {
+    let mut output = String::new();
+    // Writing to a `String` cannot fail.
+    let _ = self.render_to(&mut output, config);
+    output
+  }
+
+  /// Like `self.render(..)`, but writes the rendered diagnostic to `writer`.
+  pub fn render_to<W: Write>(&self, writer: &mut W, config: &RenderConfig) -> fmt::Result {
+    Renderer::new(self, *config).render(writer)
+  }
+}
+
+/// Renders the diagnostic without colors.
+impl<'n, 't, T: SourceText> Display for Diagnostic<'n, 't, T> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    self.render_to(f, &RenderConfig::default())
+  }
+}
+
+
+/// The ANSI styles of the parts of a rendered diagnostic.
+const GUTTER: &str = "1;34";
+const SECONDARY: &str = "1;34";
+const MESSAGE: &str = "1";
+const REMOVED: &str = "1;31";
+const ADDED: &str = "1;32";
+
+fn severity_style(severity: Severity) -> &'static str {
+  match severity {
+    Severity::Bug | Severity::Error => "1;31",
+    Severity::Warning => "1;33",
+    Severity::Note => "1;32",
+    Severity::Help => "1;36",
+  }
+}
+
+
+/// A label's span in display columns. Lines are 0-based, and `end_column` is exclusive.
+struct Marked<'d> {
+  style       : LabelStyle,
+  message     : &'d str,
+  start_line  : usize,
+  start_column: usize,
+  end_line    : usize,
+  end_column  : usize,
+}
+
+impl Marked<'_> {
+  fn is_multiline(&self) -> bool {
+    self.start_line != self.end_line
+  }
+}
+
+/// The labels of a diagnostic that belong to one source.
+struct Snippet<'d, 'n, 't, T: SourceText> {
+  source : &'t Source<'n, T>,
+  /// The span whose location is given in the snippet's `-->` line.
+  locus  : Span<'n, 't, T>,
+  labels : Vec<Marked<'d>>,
+}
+
+impl<'d, 'n, 't, T: SourceText> Snippet<'d, 'n, 't, T> {
+  /// The lines to display, in order. Long multi-line labels only show their first and last two
+  /// lines.
+  fn lines(&self) -> Vec<usize> {
+    let mut lines = Vec::new();
+    for label in &self.labels {
+      if label.end_line - label.start_line <= 4 {
+        lines.extend(label.start_line..=label.end_line);
+      } else {
+        lines.extend([label.start_line, label.start_line + 1, label.end_line - 1, label.end_line]);
+      }
+    }
+    lines.sort_unstable();
+    lines.dedup();
+    lines
+  }
+}
+
+
+struct Renderer<'d, 'n, 't, T: SourceText> {
+  diagnostic  : &'d Diagnostic<'n, 't, T>,
+  config      : RenderConfig,
+  snippets    : Vec<Snippet<'d, 'n, 't, T>>,
+  /// The width of the line numbers in the gutter.
+  gutter_width: usize,
+}
+
+impl<'d, 'n, 't, T: SourceText> Renderer<'d, 'n, 't, T> {
+  fn new(diagnostic: &'d Diagnostic<'n, 't, T>, config: RenderConfig) -> Self {
+    // Group the labels by source, starting with the source of the first primary label.
+    let mut labels: Vec<_> = diagnostic.labels.iter().collect();
+    labels.sort_by_key(|label| label.style);
+
+    let mut snippets: Vec<Snippet<T>> = Vec::new();
+    for label in labels {
+      let Some(marked) = mark(label.span, label.style, &label.message) else { continue };
+      match snippets.iter_mut().find(|snippet| std::ptr::eq(snippet.source, label.span.source)) {
+        Some(snippet) => snippet.labels.push(marked),
+        None => snippets.push(
+          Snippet {
+            source: label.span.source,
+            locus : label.span,
+            labels: vec![marked],
+          }
+        ),
+      }
+    }
+
+    let last_line =
+        snippets.iter()
+                .flat_map(|snippet| snippet.lines().last().copied())
+                .chain(diagnostic.suggestions.iter().flat_map(|suggestion| {
+                  let lines = suggestion.span.line_range().ok()?;
+                  Some(usize::from(*lines.end()) + suggestion.replacement.matches('\n').count())
+                }))
+                .max()
+                .unwrap_or(0);
+
+    Renderer {
+      diagnostic,
+      config,
+      snippets,
+      gutter_width: (last_line + 1).to_string().len(),
+    }
+  }
+
+  fn paint(&self, style: &str, text: &str) -> String {
+    match self.config.color && !text.is_empty() {
+      true => format!("\x1b[{}m{}\x1b[0m", style, text),
+      false => text.to_string(),
+    }
+  }
+
+  fn mark_style(&self, style: LabelStyle) -> &'static str {
+    match style {
+      LabelStyle::Primary => severity_style(self.diagnostic.severity),
+      LabelStyle::Secondary => SECONDARY,
+    }
+  }
+
+  fn render<W: Write>(&self, writer: &mut W) -> fmt::Result {
+    let diagnostic = self.diagnostic;
+
+    let mut header = diagnostic.severity.to_string();
+    if let Some(code) = &diagnostic.code {
+      write!(header, "[{}]", code)?;
+    }
+    write!(writer, "{}", self.paint(severity_style(diagnostic.severity), &header))?;
+    if !diagnostic.message.is_empty() {
+      write!(writer, "{}", self.paint(MESSAGE, &format!(": {}", diagnostic.message)))?;
+    }
+    writeln!(writer)?;
+
+    for (i, snippet) in self.snippets.iter().enumerate() {
+      self.render_snippet(writer, snippet, i == 0)?;
+    }
+
+    if !diagnostic.notes.is_empty() && !self.snippets.is_empty() {
+      self.write_gutter(writer, "")?;
+      writeln!(writer)?;
+    }
+    for note in &diagnostic.notes {
+      let indent = " ".repeat(self.gutter_width + 3);
+      write!(writer, "{} {} ", " ".repeat(self.gutter_width), self.paint(GUTTER, "="))?;
+      writeln!(writer, "{}", note.replace('\n', &format!("\n{}", indent)))?;
+    }
+
+    for suggestion in &diagnostic.suggestions {
+      self.render_suggestion(writer, suggestion)?;
+    }
+
+    Ok(())
+  }
+
+  /// Writes a `help` section showing the lines `suggestion` changes. A replacement within a single
+  /// line is shown applied, with the new text marked by `~`, or by `+` for an insertion. Any other
+  /// change is shown as a diff of the old lines, marked `-`, and the new lines, marked `+`.
+  fn render_suggestion<W: Write>(&self, writer: &mut W, suggestion: &Suggestion<'n, 't, T>) -> fmt::Result {
+    let span   = suggestion.span;
+    let source = span.source;
+    let (Ok(first), Ok(last)) = (source.line_index(span.start()), source.line_index(span.end())) else {
+      return Ok(());
+    };
+
+    write!(writer, "{}", self.paint(severity_style(Severity::Help), "help"))?;
+    if !suggestion.message.is_empty() {
+      write!(writer, "{}", self.paint(MESSAGE, &format!(": {}", suggestion.message)))?;
+    }
+    writeln!(writer)?;
+    if !self.snippets.first().is_some_and(|snippet| std::ptr::eq(snippet.source, source)) {
+      let arrow = if self.snippets.is_empty() { "-->" } else { ":::" };
+      write!(writer, "{}{} {}", " ".repeat(self.gutter_width), self.paint(GUTTER, arrow), source.name())?;
+      if let Ok(location) = span.location() {
+        write!(writer, ":{}:{}", location.line_number(), location.column_number())?;
+      }
+      writeln!(writer)?;
+    }
+    self.write_gutter(writer, "")?;
+    writeln!(writer)?;
+
+    // The text of the lines the suggestion touches, from the start of the first to the end of the
+    // line the rest of the text continues on, before and after the change.
+    let start  = source.line_span_without_terminator(first).map_or(span.start(), |line| line.start());
+    let end    = source.line_span_without_terminator(last).map_or(span.end(), |line| line.end());
+    let (start, end) = (usize::from(start), usize::from(end.max(span.end())));
+    let text   = source.text();
+    let prefix = text.subslice(start..usize::from(span.start())).to_str_lossy();
+    let suffix = text.subslice(usize::from(span.end())..end).to_str_lossy();
+    let old    = text.subslice(start..end).to_str_lossy();
+    let new    = format!("{}{}{}", prefix, suggestion.replacement, suffix);
+    let first  = usize::from(first);
+
+    let encoding = display_encoding(source);
+    if first == usize::from(last) && !suggestion.replacement.is_empty() && !suggestion.replacement.contains('\n') {
+      let line  = DisplayLine::from_bytes(ByteIndex::default(), new.as_bytes(), encoding);
+      let from  = line.column_of(ByteIndex::new(prefix.len()));
+      let to    = line.column_of(ByteIndex::new(prefix.len() + suggestion.replacement.len()));
+      let mark  = if suggestion.is_insertion() { "+" } else { "~" };
+      self.write_gutter(writer, &(first + 1).to_string())?;
+      writeln!(writer, " {}", line.text.trim_end())?;
+      self.write_gutter(writer, "")?;
+      writeln!(writer, " {}{}", " ".repeat(from), self.paint(ADDED, &mark.repeat((to - from).max(1))))?;
+      return Ok(());
+    }
+
+    let mut old: Vec<&str> = old.split('\n').map(|line| line.trim_end_matches('\r')).collect();
+    let mut new: Vec<&str> = new.split('\n').map(|line| line.trim_end_matches('\r')).collect();
+    // Only show the lines that differ.
+    let mut line = first;
+    while old.len() > 1 && new.len() > 1 && old[0] == new[0] {
+      old.remove(0);
+      new.remove(0);
+      line += 1;
+    }
+    while !old.is_empty() && !new.is_empty() && old.last() == new.last() {
+      old.pop();
+      new.pop();
+    }
+
+    for (lines, mark, style) in [(&old, "-", REMOVED), (&new, "+", ADDED)] {
+      for (i, text) in lines.iter().enumerate() {
+        let number = format!("{:>width$} ", line + i + 1, width = self.gutter_width);
+        let text   = DisplayLine::from_bytes(ByteIndex::default(), text.as_bytes(), encoding).text;
+        let row    = format!("{} {}", self.paint(style, mark), text);
+        writeln!(writer, "{}{}", self.paint(GUTTER, &number), row.trim_end())?;
+      }
+    }
+
+    Ok(())
+  }
+
+  fn render_snippet<W: Write>(&self, writer: &mut W, snippet: &Snippet<T>, first: bool) -> fmt::Result {
+    let arrow = if first { "-->" } else { ":::" };
+    write!(writer, "{}{} {}", " ".repeat(self.gutter_width), self.paint(GUTTER, arrow), snippet.source.name())?;
+    if let Ok(location) = snippet.locus.location() {
+      write!(writer, ":{}:{}", location.line_number(), location.column_number())?;
+    }
+    writeln!(writer)?;
+    self.write_gutter(writer, "")?;
+    writeln!(writer)?;
+
+    // Labels that start earlier and end later get gutter columns further left, so nested labels
+    // don't cross.
+    let mut multiline: Vec<&Marked> = snippet.labels.iter().filter(|label| label.is_multiline()).collect();
+    multiline.sort_by_key(|label| {
+      (label.start_line, label.start_column, Reverse(label.end_line), Reverse(label.end_column))
+    });
+    let mut single: Vec<&Marked> = snippet.labels.iter().filter(|label| !label.is_multiline()).collect();
+    single.sort_by_key(|label| (label.start_column, label.end_column));
+
+    // Whether the gutter line of each multi-line label has been started.
+    let mut active   = vec![false; multiline.len()];
+    let mut previous = None;
+
+    for line in snippet.lines() {
+      if previous.is_some_and(|previous| line > previous + 1) {
+        writeln!(writer, "{}", self.paint(GUTTER, "..."))?;
+      }
+      previous = Some(line);
+
+      let text = DisplayLine::new(snippet.source, line);
+      self.write_gutter(writer, &(line + 1).to_string())?;
+      let content = format!("{}{}", self.bars(&multiline, &active, None), text.text);
+      match content.trim_end() {
+        "" => writeln!(writer)?,
+        content => writeln!(writer, " {}", content)?,
+      }
+
+      for label in single.iter().filter(|label| label.start_line == line) {
+        let mark  = if label.style == LabelStyle::Primary { "^" } else { "-" };
+        let width = label.end_column.saturating_sub(label.start_column).max(1);
+        let underline = format!("{}{}", " ".repeat(label.start_column), mark.repeat(width));
+        self.write_annotation(writer, &multiline, &active, None, &underline, label)?;
+      }
+
+      for (k, label) in multiline.iter().enumerate().filter(|(_, label)| label.start_line == line) {
+        let mark = if label.style == LabelStyle::Primary { "^" } else { "-" };
+        let connector = format!("{}{}", "_".repeat(multiline.len() - k + label.start_column), mark);
+        self.write_gutter(writer, "")?;
+        let bars = self.bars(&multiline, &active, Some((k, ' ')));
+        writeln!(writer, " {}{}", bars, self.paint(self.mark_style(label.style), &connector))?;
+        active[k] = true;
+      }
+
+      for (k, label) in multiline.iter().enumerate().filter(|(_, label)| label.end_line == line) {
+        let mark = if label.style == LabelStyle::Primary { "^" } else { "-" };
+        let connector = format!(
+          "{}{}",
+          "_".repeat(multiline.len() - k + label.end_column.saturating_sub(1)),
+          mark
+        );
+        self.write_annotation(writer, &multiline, &active, Some((k, '|')), &connector, label)?;
+        active[k] = false;
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Writes the gutter of a row, with `number` right-aligned in it.
+  fn write_gutter<W: Write>(&self, writer: &mut W, number: &str) -> fmt::Result {
+    let gutter = format!("{:>width$} |", number, width = self.gutter_width);
+    write!(writer, "{}", self.paint(GUTTER, &gutter))
+  }
+
+  /// Writes a row holding the underline or connector `marks` of `label` and its message.
+  fn write_annotation<W: Write>(
+    &self,
+    writer   : &mut W,
+    multiline: &[&Marked],
+    active   : &[bool],
+    connector: Option<(usize, char)>,
+    marks    : &str,
+    label    : &Marked
+  ) -> fmt::Result
+  {
+    self.write_gutter(writer, "")?;
+    let bars = self.bars(multiline, active, connector);
+    let (padding, marks) = marks.split_at(marks.len() - marks.trim_start().len());
+    write!(writer, " {}{}{}", bars, padding, self.paint(self.mark_style(label.style), marks))?;
+    if !label.message.is_empty() {
+      write!(writer, " {}", self.paint(self.mark_style(label.style), label.message))?;
+    }
+    writeln!(writer)
+  }
+
+  /// The gutter columns of the multi-line labels, followed by the space separating them from the
+  /// text. If `connector` is `Some((k, c))`, the column of the `k`th label is `c` and the columns
+  /// after it are left for the caller to draw the label's connector through.
+  fn bars(&self, multiline: &[&Marked], active: &[bool], connector: Option<(usize, char)>) -> String {
+    if multiline.is_empty() {
+      return String::new();
+    }
+
+    let mut bars = String::new();
+    for (k, label) in multiline.iter().enumerate() {
+      match connector {
+        Some((j, c)) if j == k => {
+          bars.push_str(&self.paint(self.mark_style(label.style), &c.to_string()));
+          return bars;
+        }
+        _ if active[k] => bars.push_str(&self.paint(self.mark_style(label.style), "|")),
+        _ => bars.push(' '),
+      }
+    }
+    bars.push(' ');
+    bars
+  }
+}
+
+
+/// Locates `span` in display columns, or gives `None` if it lies outside of its source.
+fn mark<'d, T: SourceText>(span: Span<'_, '_, T>, style: LabelStyle, message: &'d str)
+  -> Option<Marked<'d>>
+{
+  let lines      = span.line_range().ok()?;
+  let start_line = usize::from(*lines.start());
+  let end_line   = usize::from(*lines.end());
+
+  let start_column = DisplayLine::new(span.source, start_line).column_of(span.start());
+  let end_column   =
+      match span.is_empty() {
+        true => start_column + 1,
+        false => DisplayLine::new(span.source, end_line).column_of(span.end()),
+      };
+
+  Some(
+    Marked {
+      style,
+      message,
+      start_line,
+      start_column,
+      end_line,
+      end_column,
+    }
+  )
+}
+
+
+/// A line of a source as it is displayed, with tabs expanded and invalid UTF-8 replaced.
+struct DisplayLine {
+  /// The index of the first byte of the line.
+  start: ByteIndex,
+  /// The length of the line in bytes, not counting its terminator.
+  len  : usize,
+  text : String,
+  /// The units of the line and the display column each ends at.
+  units: Vec<Unit>,
+}
+
+impl DisplayLine {
+  fn new<T: SourceText>(source: &Source<'_, T>, line: usize) -> DisplayLine {
+    let Ok(span) = source.line_span_without_terminator(LineIndex::new(line)) else {
+      return DisplayLine { start: source.end(), len: 0, text: String::new(), units: Vec::new() };
+    };
+
+    DisplayLine::from_bytes(span.start(), span.fragment().as_bytes(), display_encoding(source))
+  }
+
+  /// The display line of the text `bytes` starting at `start`, which holds no line terminator.
+  fn from_bytes(start: ByteIndex, bytes: &[u8], encoding: PositionEncoding) -> DisplayLine {
+    let units: Vec<Unit> = encoding.units(bytes).collect();
+    let mut text   = String::new();
+    let mut column = 0;
+    for unit in &units {
+      match &bytes[unit.bytes.clone()] {
+        b"\t" => text.push_str(&" ".repeat(unit.column - column)),
+        piece => text.push_str(&String::from_utf8_lossy(piece)),
+      }
+      column = unit.column;
+    }
+
+    DisplayLine { start, len: bytes.len(), text, units }
+  }
+
+  /// The display column at which the byte at `index` starts. The terminator of the line is
+  /// treated as a single column following the text.
+  fn column_of(&self, index: ByteIndex) -> usize {
+    let width  = self.units.last().map_or(0, |unit| unit.column);
+    let offset = usize::from(index).saturating_sub(usize::from(self.start));
+    if offset >= self.len {
+      return width + (offset > self.len) as usize;
+    }
+
+    match self.units.partition_point(|unit| unit.bytes.end <= offset) {
+      0 => 0,
+      n => self.units[n - 1].column,
+    }
+  }
+}
+
+/// The encoding giving the columns text occupies in a terminal.
+fn display_encoding<T: SourceText>(source: &Source<'_, T>) -> PositionEncoding {
+  #[cfg(feature = "unicode-columns")]
+  return PositionEncoding::DisplayWidth { tab_width: source.tab_width() };
+  #[cfg(not(feature = "unicode-columns"))]
+  return PositionEncoding::Visual { tab_width: source.tab_width() };
+}
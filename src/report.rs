@@ -0,0 +1,562 @@
+/*!
+
+Machine-readable forms of `Diagnostic`s for tools that collect diagnostics rather than show them to
+a user, such as CI and code scanning dashboards. Both are plain data types implementing `Serialize`
+and `Deserialize`, so they can be written with any `serde` format.
+
+ * `JsonDiagnostic` follows the schema of rustc's `--error-format=json`: one object per
+   diagnostic, with its labels as `spans` and its notes and suggestions as `children`. As in
+   rustc, lines and columns are 1-based, columns count characters whatever the sources'
+   encodings, and the end column is exclusive. Each span carries the text of the lines it touches
+   and, if it was built with `JsonDiagnostic::with_expansions`, the macro expansions that produced
+   it.
+ * `SarifLog` is a [SARIF 2.1.0] log with one run holding a result per diagnostic. SARIF counts
+   columns in UTF-16 code units, so the regions of a `SarifLog` do too, whatever the sources'
+   encodings. Byte offsets are into the files as they are on disk. (See
+   `Source::original_offset`.) Source names become URI references, with absolute paths as
+   `file://` URIs.
+
+Both take file names from `Source::name`.
+
+```
+use saucepan::{Diagnostic, JsonDiagnostic, Label, SarifLog, Source};
+
+let source = Source::new("main.x", "let x = 1 + true;\n");
+let diagnostic =
+    Diagnostic::error()
+        .with_code("E0308")
+        .with_message("mismatched types")
+        .with_label(Label::primary(source.slice(12..16)).with_message("expected integer"));
+
+let json = JsonDiagnostic::new(&diagnostic);
+assert_eq!((json.spans[0].line_start, json.spans[0].column_start), (1, 13));
+
+let sarif = SarifLog::new("checker", [&diagnostic]);
+assert_eq!(sarif.runs[0].results[0].rule_id.as_deref(), Some("E0308"));
+```
+
+[SARIF 2.1.0]: https://docs.oasis-open.org/sarif/sarif/v2.1.0/sarif-v2.1.0.html
+
+*/
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  Applicability,
+  Diagnostic,
+  Expansion,
+  LabelStyle,
+  LineIndex,
+  PositionEncoding,
+  Severity,
+  SourceText,
+  Sources,
+  Span,
+  Suggestion,
+  TextSlice,
+};
+
+
+/// A diagnostic in the schema of rustc's `--error-format=json`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct JsonDiagnostic {
+  /// Always `"diagnostic"`.
+  #[serde(rename = "$message_type")]
+  pub message_type: String,
+  pub message     : String,
+  pub code        : Option<JsonCode>,
+  /// One of `"error: internal compiler error"`, `"error"`, `"warning"`, `"note"`, or `"help"`.
+  pub level       : String,
+  pub spans       : Vec<JsonSpan>,
+  /// The notes of the diagnostic, as diagnostics of level `"note"` without spans, followed by its
+  /// suggestions, as diagnostics of level `"help"` with a single span.
+  pub children    : Vec<JsonDiagnostic>,
+  /// The diagnostic as rendered by `Diagnostic::render`.
+  pub rendered    : Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct JsonCode {
+  pub code       : String,
+  pub explanation: Option<String>,
+}
+
+/// A label of a `JsonDiagnostic`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct JsonSpan {
+  pub file_name               : String,
+  pub byte_start              : usize,
+  pub byte_end                : usize,
+  pub line_start              : usize,
+  pub line_end                : usize,
+  pub column_start            : usize,
+  pub column_end              : usize,
+  pub is_primary              : bool,
+  /// The lines the span touches, each with the part the span covers.
+  pub text                    : Vec<JsonSpanLine>,
+  pub label                   : Option<String>,
+  pub suggested_replacement   : Option<String>,
+  pub suggestion_applicability: Option<Applicability>,
+  /// The macro expansion that produced the span, if it lies in a synthetic source.
+  pub expansion               : Option<Box<JsonExpansion>>,
+}
+
+/// A line touched by a `JsonSpan`. The highlight columns are 1-based character columns, and
+/// `highlight_end` is exclusive.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct JsonSpanLine {
+  /// The text of the line without its terminator.
+  pub text           : String,
+  pub highlight_start: usize,
+  pub highlight_end  : usize,
+}
+
+/// The macro expansion that produced a `JsonSpan`. (See `Expansion`.)
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct JsonExpansion {
+  /// The macro invocation, with the expansion it lies in if it is itself synthetic.
+  pub span           : JsonSpan,
+  /// The name of the synthetic source holding the expansion.
+  pub macro_decl_name: String,
+  pub def_site_span  : JsonSpan,
+}
+
+impl JsonDiagnostic {
+  /// The diagnostic with the `expansion` of every span left out. (See
+  /// `JsonDiagnostic::with_expansions`.)
+  pub fn new<T: SourceText>(diagnostic: &Diagnostic<'_, '_, T>) -> JsonDiagnostic {
+    JsonDiagnostic::build(diagnostic, None)
+  }
+
+  /// Like `JsonDiagnostic::new`, but spans in synthetic sources are given the stack of macro
+  /// expansions that produced them. The sources of the diagnostic must belong to `sources`.
+  pub fn with_expansions<'n, T>(diagnostic: &Diagnostic<'n, '_, T>, sources: &Sources<'n, T>)
+    -> JsonDiagnostic
+    where T: SourceText
+  {
+    JsonDiagnostic::build(diagnostic, Some(sources))
+  }
+
+  fn build<'n, T: SourceText>(diagnostic: &Diagnostic<'n, '_, T>, sources: Option<&Sources<'n, T>>)
+    -> JsonDiagnostic
+  {
+    let level = match diagnostic.severity {
+      Severity::Bug => "error: internal compiler error",
+      Severity::Error => "error",
+      Severity::Warning => "warning",
+      Severity::Note => "note",
+      Severity::Help => "help",
+    };
+
+    JsonDiagnostic {
+      message_type: "diagnostic".to_string(),
+      message     : diagnostic.message.clone(),
+      code        : diagnostic.code.clone().map(|code| JsonCode { code, explanation: None }),
+      level       : level.to_string(),
+      spans       : diagnostic.labels
+                              .iter()
+                              .filter_map(|label| {
+                                JsonSpan::new(label.span, label.style, &label.message, sources)
+                              })
+                              .collect(),
+      children    : diagnostic.notes
+                              .iter()
+                              .map(|note| JsonDiagnostic::child("note", note, None))
+                              .chain(diagnostic.suggestions.iter().map(|suggestion| {
+                                let span = JsonSpan::suggestion(suggestion, sources);
+                                JsonDiagnostic::child("help", &suggestion.message, span)
+                              }))
+                              .collect(),
+      rendered    : Some(diagnostic.render(&Default::default())),
+    }
+  }
+
+  fn child(level: &str, message: &str, span: Option<JsonSpan>) -> JsonDiagnostic {
+    JsonDiagnostic {
+      message_type: "diagnostic".to_string(),
+      message     : message.to_string(),
+      code        : None,
+      level       : level.to_string(),
+      spans       : span.into_iter().collect(),
+      children    : Vec::new(),
+      rendered    : None,
+    }
+  }
+}
+
+impl JsonSpan {
+  /// Gives `None` if `span` lies outside of its source.
+  fn new<'n, T: SourceText>(
+    span   : Span<'n, '_, T>,
+    style  : LabelStyle,
+    label  : &str,
+    sources: Option<&Sources<'n, T>>
+  ) -> Option<JsonSpan>
+  {
+    let start = span.location_in(PositionEncoding::Utf32).ok()?;
+    let end   = span.source.location_in(span.end(), PositionEncoding::Utf32).ok()?;
+    let text: Vec<JsonSpanLine> =
+        span.lines().map(|(line, piece)| JsonSpanLine::new(line, piece)).collect::<Option<_>>()?;
+    let expansion =
+        sources.and_then(|sources| JsonExpansion::new(span.source.name(), span.expansion()?, sources));
+
+    Some(
+      JsonSpan {
+        file_name               : span.source.name().to_string(),
+        byte_start              : span.start().into(),
+        byte_end                : span.end().into(),
+        line_start              : start.line_number().0 as usize,
+        line_end                : end.line_number().0 as usize,
+        column_start            : start.column_number().0 as usize,
+        column_end              : end.column_number().0 as usize,
+        is_primary              : style == LabelStyle::Primary,
+        text,
+        label                   : (!label.is_empty()).then(|| label.to_string()),
+        suggested_replacement   : None,
+        suggestion_applicability: None,
+        expansion               : expansion.map(Box::new),
+      }
+    )
+  }
+
+  fn suggestion<'n, T>(suggestion: &Suggestion<'n, '_, T>, sources: Option<&Sources<'n, T>>)
+    -> Option<JsonSpan>
+    where T: SourceText
+  {
+    Some(
+      JsonSpan {
+        suggested_replacement   : Some(suggestion.replacement.clone()),
+        suggestion_applicability: Some(suggestion.applicability),
+        ..JsonSpan::new(suggestion.span, LabelStyle::Primary, "", sources)?
+      }
+    )
+  }
+}
+
+impl JsonSpanLine {
+  /// The line with index `line`, highlighting `piece`, the part of a span on the line.
+  fn new<T: SourceText>(line: LineIndex, piece: Span<'_, '_, T>) -> Option<JsonSpanLine> {
+    let source = piece.source;
+    let text   = source.line_span_without_terminator(line).ok()?.fragment();
+    let column = |index| {
+      let location = source.location_in(index, PositionEncoding::Utf32).ok()?;
+      Some(location.column_number().0 as usize)
+    };
+
+    Some(
+      JsonSpanLine {
+        text           : text.to_str_lossy().into_owned(),
+        highlight_start: column(piece.start())?,
+        highlight_end  : column(piece.end())?,
+      }
+    )
+  }
+}
+
+impl JsonExpansion {
+  /// The expansion `expansion` that produced the synthetic source named `name`. Gives `None` if
+  /// its spans are not in `sources`.
+  fn new<'n, T: SourceText>(name: &str, expansion: Expansion, sources: &Sources<'n, T>)
+    -> Option<JsonExpansion>
+  {
+    let call_site  = sources.span(expansion.call_site).ok()?;
+    let definition = sources.span(expansion.definition).ok()?;
+
+    Some(
+      JsonExpansion {
+        span           : JsonSpan::new(call_site, LabelStyle::Secondary, "", Some(sources))?,
+        macro_decl_name: name.to_string(),
+        def_site_span  : JsonSpan::new(definition, LabelStyle::Secondary, "", Some(sources))?,
+      }
+    )
+  }
+}
+
+
+const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+const SARIF_VERSION: &str = "2.1.0";
+
+/// A SARIF 2.1.0 log holding the results of a single run of a tool. Only the properties saucepan
+/// can fill in are modeled.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct SarifLog {
+  #[serde(rename = "$schema")]
+  pub schema : String,
+  pub version: String,
+  pub runs   : Vec<SarifRun>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SarifRun {
+  pub tool       : SarifTool,
+  /// Always `"utf16CodeUnits"`.
+  pub column_kind: String,
+  pub results    : Vec<SarifResult>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct SarifTool {
+  pub driver: SarifDriver,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct SarifDriver {
+  pub name : String,
+  /// A rule for each distinct diagnostic code, in order of first appearance.
+  pub rules: Vec<SarifRule>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct SarifRule {
+  pub id: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SarifResult {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub rule_id          : Option<String>,
+  /// One of `"error"`, `"warning"`, or `"note"`.
+  pub level            : String,
+  /// The message of the diagnostic, followed by its notes on separate lines.
+  pub message          : SarifMessage,
+  /// The primary labels of the diagnostic.
+  pub locations        : Vec<SarifLocation>,
+  /// The secondary labels of the diagnostic.
+  #[serde(skip_serializing_if = "Vec::is_empty", default)]
+  pub related_locations: Vec<SarifLocation>,
+  /// The suggestions of the diagnostic.
+  #[serde(skip_serializing_if = "Vec::is_empty", default)]
+  pub fixes            : Vec<SarifFix>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct SarifMessage {
+  pub text: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SarifLocation {
+  pub physical_location: SarifPhysicalLocation,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub message          : Option<SarifMessage>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SarifPhysicalLocation {
+  pub artifact_location: SarifArtifactLocation,
+  pub region           : SarifRegion,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct SarifArtifactLocation {
+  /// The name of the source as a URI reference, a `file://` URI if it is an absolute path.
+  pub uri: String,
+}
+
+/// A region of a source. Lines and columns are 1-based, and `end_column` is exclusive. The byte
+/// offset and length are measured in the original file, including any byte-order mark.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SarifRegion {
+  pub start_line  : usize,
+  pub start_column: usize,
+  pub end_line    : usize,
+  pub end_column  : usize,
+  pub byte_offset : usize,
+  pub byte_length : usize,
+}
+
+/// A suggestion, as a change to a single artifact.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SarifFix {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub description     : Option<SarifMessage>,
+  pub artifact_changes: Vec<SarifArtifactChange>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SarifArtifactChange {
+  pub artifact_location: SarifArtifactLocation,
+  pub replacements     : Vec<SarifReplacement>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SarifReplacement {
+  pub deleted_region  : SarifRegion,
+  pub inserted_content: SarifArtifactContent,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct SarifArtifactContent {
+  pub text: String,
+}
+
+impl SarifLog {
+  /// A log of a run of the tool named `tool` that reported `diagnostics`.
+  pub fn new<'d, 'n: 't, 't: 'd, T, I>(tool: &str, diagnostics: I) -> SarifLog
+    where T: SourceText + 't,
+          I: IntoIterator<Item=&'d Diagnostic<'n, 't, T>>
+  {
+    let mut rules: Vec<SarifRule> = Vec::new();
+    let mut results = Vec::new();
+
+    for diagnostic in diagnostics {
+      if let Some(code) = &diagnostic.code {
+        if !rules.iter().any(|rule| &rule.id == code) {
+          rules.push(SarifRule { id: code.clone() });
+        }
+      }
+      results.push(SarifResult::new(diagnostic));
+    }
+
+    SarifLog {
+      schema : SARIF_SCHEMA.to_string(),
+      version: SARIF_VERSION.to_string(),
+      runs   : vec![
+        SarifRun {
+          tool       : SarifTool { driver: SarifDriver { name: tool.to_string(), rules } },
+          column_kind: "utf16CodeUnits".to_string(),
+          results,
+        }
+      ],
+    }
+  }
+}
+
+impl SarifResult {
+  fn new<T: SourceText>(diagnostic: &Diagnostic<'_, '_, T>) -> SarifResult {
+    let level = match diagnostic.severity {
+      Severity::Bug | Severity::Error => "error",
+      Severity::Warning => "warning",
+      Severity::Note | Severity::Help => "note",
+    };
+
+    let mut text = diagnostic.message.clone();
+    for note in &diagnostic.notes {
+      text.push('\n');
+      text.push_str(note);
+    }
+
+    let (mut locations, mut related_locations) = (Vec::new(), Vec::new());
+    for label in &diagnostic.labels {
+      let Some(location) = SarifLocation::new(label.span, &label.message) else { continue };
+      match label.style {
+        LabelStyle::Primary => locations.push(location),
+        LabelStyle::Secondary => related_locations.push(location),
+      }
+    }
+
+    SarifResult {
+      rule_id: diagnostic.code.clone(),
+      level  : level.to_string(),
+      message: SarifMessage { text },
+      locations,
+      related_locations,
+      fixes  : diagnostic.suggestions.iter().filter_map(SarifFix::new).collect(),
+    }
+  }
+}
+
+impl SarifFix {
+  /// Gives `None` if the span of `suggestion` lies outside of its source.
+  fn new<T: SourceText>(suggestion: &Suggestion<'_, '_, T>) -> Option<SarifFix> {
+    Some(
+      SarifFix {
+        description     : (!suggestion.message.is_empty())
+                              .then(|| SarifMessage { text: suggestion.message.clone() }),
+        artifact_changes: vec![
+          SarifArtifactChange {
+            artifact_location: SarifArtifactLocation::new(suggestion.span.source.name()),
+            replacements     : vec![
+              SarifReplacement {
+                deleted_region  : SarifRegion::new(suggestion.span)?,
+                inserted_content: SarifArtifactContent { text: suggestion.replacement.clone() },
+              }
+            ],
+          }
+        ],
+      }
+    )
+  }
+}
+
+impl SarifLocation {
+  /// Gives `None` if `span` lies outside of its source.
+  fn new<T: SourceText>(span: Span<'_, '_, T>, message: &str) -> Option<SarifLocation> {
+    Some(
+      SarifLocation {
+        physical_location: SarifPhysicalLocation {
+          artifact_location: SarifArtifactLocation::new(span.source.name()),
+          region           : SarifRegion::new(span)?,
+        },
+        message: (!message.is_empty()).then(|| SarifMessage { text: message.to_string() }),
+      }
+    )
+  }
+}
+
+impl SarifRegion {
+  /// Gives `None` if `span` lies outside of its source.
+  fn new<T: SourceText>(span: Span<'_, '_, T>) -> Option<SarifRegion> {
+    let start = span.location_in(PositionEncoding::Utf16).ok()?;
+    let end   = span.source.location_in(span.end(), PositionEncoding::Utf16).ok()?;
+    let (start_offset, end_offset) =
+        (span.source.original_offset(span.start()), span.source.original_offset(span.end()));
+
+    Some(
+      SarifRegion {
+        start_line  : start.line_number().0 as usize,
+        start_column: start.column_number().0 as usize,
+        end_line    : end.line_number().0 as usize,
+        end_column  : end.column_number().0 as usize,
+        byte_offset : start_offset,
+        byte_length : end_offset - start_offset,
+      }
+    )
+  }
+}
+
+impl SarifArtifactLocation {
+  fn new(name: &str) -> SarifArtifactLocation {
+    SarifArtifactLocation { uri: artifact_uri(name) }
+  }
+}
+
+/// Turns the source name `name` into a URI reference. Absolute Unix and Windows paths become
+/// `file://` URIs, and any other name a relative reference. Backslashes are taken as path
+/// separators, and every byte but an unreserved character or `/` is percent-encoded.
+pub(crate) fn artifact_uri(name: &str) -> String {
+  let path = name.replace('\\', "/");
+  let bytes = path.as_bytes();
+
+  match bytes {
+    // A UNC path, `//server/share/...`.
+    [b'/', b'/', ..] => format!("file:{}", percent_encode(&path)),
+    [b'/', ..] => format!("file://{}", percent_encode(&path)),
+    // A path with a drive letter, `C:/...`.
+    [drive, b':', b'/', ..] if drive.is_ascii_alphabetic() => {
+      format!("file:///{}:{}", *drive as char, percent_encode(&path[2..]))
+    }
+    _ => percent_encode(&path),
+  }
+}
+
+fn percent_encode(text: &str) -> String {
+  let mut encoded = String::with_capacity(text.len());
+  for &byte in text.as_bytes() {
+    match byte {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+        encoded.push(byte as char)
+      }
+      _ => encoded.push_str(&format!("%{:02X}", byte)),
+    }
+  }
+  encoded
+}
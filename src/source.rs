@@ -8,27 +8,35 @@ the `Sources` instance in exchange for a `Span` covering the entirety of the sou
 `Span` may be used both as an input and an output, e.g. the input span is broken into token
 spans. A `Span` knows its `Source` and can be queried for `&str`s and position/location data.
 
+The text of a `Source` may be either a `&str` or a `&[u8]`. (See the `SourceText` trait.) All
+indices into the text are byte indices regardless of the text type. Columns of `Location`s are
+counted in the source's `PositionEncoding`, characters by default. Lines are split on the
+source's `LineTerminators`, only `\n` by default. The text is kept byte for byte, including any
+byte-order mark; `Source::decode` strips it instead. (See the `encoding` module.)
+
 Example:
 
 ```rust
+use saucepan::Source;
+
+let source = Source::new("example.txt", "one\ntwo\n");
+let bytes  = Source::new("example.bin", b"one\ntwo\n".as_slice());
 
+assert_eq!(source.slice(4..7).fragment(), "two");
+assert_eq!(bytes.slice(4..7).fragment(), b"two");
 ```
 
 */
 
 use std::{
-  cmp::{
-    max,
-    min,
-    Ordering
-  },
-  ops::{
-    Bound,
-    Range,
-    RangeBounds
-  },
+  borrow::Cow,
+  cmp::Ordering,
+  fmt::{Debug, Display},
+  io,
+  ops::{Bound, RangeBounds},
 };
-use std::fmt::{Debug, Display};
+#[cfg(feature = "reporting")]
+use std::ops::Range;
 
 
 #[cfg(feature = "serialization")]
@@ -38,28 +46,49 @@ use nom_locate::LocatedSpan;
 #[cfg(feature = "reporting")]
 use codespan_reporting::files::{Files, Error as CodespanError};
 
-use memchr::Memchr;
 use bytecount::{naive_num_chars, num_chars};
 
 use crate::{
   error::{
+    IndexOverflowError,
     LineIndexOutOfBoundsError,
     LocationError,
     // NotASourceError
   },
+  AsBytes,
   ByteIndex,
   ByteOffset,
+  ByteOrderMark,
   ColumnIndex,
+  Edit,
+  EditableText,
+  Expansion,
+  LineEnding,
   LineIndex,
   LineOffset,
+  LineTerminators,
+  Lines,
   Location,
+  PositionEncoding,
+  Slice,
+  SourceId,
+  SourceText,
   Span,
+  TextEncoding,
+  TextSlice,
 };
+use crate::encoding::{invalid_data, OffsetMap};
+use crate::index_types::try_raw_index;
+use crate::line_ending::LineEndingCounts;
 use crate::span::Formatter;
 
 
+/// The tab width of a new `Source`.
+const DEFAULT_TAB_WIDTH: u32 = 4;
+
+
 #[cfg(feature = "nom-parsing")]
-type LSpan<'n, 't> = LocatedSpan<&'t str, &'t Source<'n, 't>>;
+type LSpan<'n, 't, T> = LocatedSpan<&'t <T as SourceText>::Slice, &'t Source<'n, T>>;
 
 
 /// A file that is stored in the database.
@@ -69,43 +98,168 @@ type LSpan<'n, 't> = LocatedSpan<&'t str, &'t Source<'n, 't>>;
   all(feature = "serialization", any(windows, unix)),
   derive(Deserialize, Serialize)
 )]
-pub struct Source<'n, 't> {
-  /// The filename.
-  name: &'n str,
-  /// The source text of the file, typically a `&str`.
-  text: &'t str,
+pub struct Source<'n, T: SourceText> {
+  /// The filename, either borrowed or owned.
+  name: Cow<'n, str>,
+  /// The source text of the file, typically a `&str`, `&[u8]`, or `String`.
+  text: T,
   /// The byte indices of line starts in the source code.
   line_starts: Vec<ByteIndex>,
+  /// The id of this source within its `Sources` database, if it has one.
+  id: Option<SourceId>,
+  /// The macro expansion that produced this source if it is synthetic.
+  expansion: Option<Expansion>,
+  /// The unit columns are counted in by `Source::location` and `Span::location`.
+  position_encoding: PositionEncoding,
+  /// The number of columns between tab stops. (See `Source::visual_column`.)
+  tab_width: u32,
+  /// The byte sequences that end a line.
+  line_terminators: LineTerminators,
+  /// The number of line endings of each style in the text.
+  line_endings: LineEndingCounts,
+  /// The byte-order mark that was stripped from the start of the file when it was decoded.
+  bom: Option<ByteOrderMark>,
+  /// The encoding the text was decoded from.
+  encoding: TextEncoding,
+  /// The map from byte indices into the text to byte offsets into the file it was decoded from.
+  offsets: OffsetMap,
 }
 
-impl<'n, 't> Source<'n, 't> {
-  pub fn new(name: &'n str, text: &'t str) -> Self {
-    let line_starts = line_starts(text.as_bytes())
-        .map(|i| ByteIndex::from(i as u32))
-        .collect();
+impl<'n, T: SourceText> Source<'n, T> {
+  /// Creates a source named `name` with the text `text`. Panics if the text is too long to be
+  /// indexed by a `RawIndex`. (See `Source::try_new`.)
+  pub fn new<N: Into<Cow<'n, str>>>(name: N, text: T) -> Self {
+    Source::try_new(name, text).unwrap_or_else(|error| panic!("{}", error))
+  }
 
-    Source {
-      name,
-      text,
-      line_starts
-    }
+  /// Like `Source::new`, but gives an error instead of panicking if the text is longer than
+  /// `RawIndex::MAX` bytes.
+  pub fn try_new<N: Into<Cow<'n, str>>>(name: N, text: T) -> Result<Self, IndexOverflowError> {
+    let name  = name.into();
+    let bytes = text.as_slice().as_bytes();
+    try_raw_index(bytes.len())?;
+    let line_starts  = line_starts(bytes, LineTerminators::default());
+    let line_endings = LineEndingCounts::scan(bytes, 0..bytes.len());
+
+    Ok(
+      Source {
+        name,
+        text,
+        line_starts,
+        id: None,
+        expansion: None,
+        position_encoding: PositionEncoding::default(),
+        tab_width: DEFAULT_TAB_WIDTH,
+        line_terminators: LineTerminators::default(),
+        line_endings,
+        bom: None,
+        encoding: TextEncoding::default(),
+        offsets: OffsetMap::default(),
+      }
+    )
   }
 
 
-  /// Note: This function requires that
-  ///   span.fragment == std::mem::transmute(
-  ///     self.text.as_bytes()[span.start().into()..span.end().into()]
-  ///   )
-  pub fn fragment(&self, span: &Span) -> &str {
-    unsafe {
-      std::mem::transmute(&self.text.as_bytes()[span.start().into()..span.end().into()])
+  /// The `SourceId` of this source if it belongs to a `Sources` database.
+  pub fn id(&self) -> Option<SourceId> {
+    self.id
+  }
+
+  pub(crate) fn set_id(&mut self, id: SourceId) {
+    self.id = Some(id);
+  }
+
+  /// The macro expansion that produced this source, or `None` if this source is not synthetic.
+  /// (See `Sources::add_expansion`.)
+  pub fn expansion(&self) -> Option<Expansion> {
+    self.expansion
+  }
+
+  pub(crate) fn set_expansion(&mut self, expansion: Expansion) {
+    self.expansion = Some(expansion);
+  }
+
+  /// The unit columns are counted in by `self.location(..)` and `Span::location`.
+  pub fn position_encoding(&self) -> PositionEncoding {
+    self.position_encoding
+  }
+
+  pub fn set_position_encoding(&mut self, position_encoding: PositionEncoding) {
+    self.position_encoding = position_encoding;
+  }
+
+  /// The number of columns between tab stops used by `self.visual_column(..)`. Defaults to 4.
+  pub fn tab_width(&self) -> u32 {
+    self.tab_width
+  }
+
+  pub fn set_tab_width(&mut self, tab_width: u32) {
+    self.tab_width = tab_width;
+  }
+
+  /// The byte sequences that end a line of this source.
+  pub fn line_terminators(&self) -> LineTerminators {
+    self.line_terminators
+  }
+
+  /// Sets the byte sequences that end a line, rebuilding the line index.
+  pub fn set_line_terminators(&mut self, line_terminators: LineTerminators) {
+    self.line_terminators = line_terminators;
+    self.line_starts      = line_starts(self.text().as_bytes(), line_terminators);
+  }
+
+  /// The line-ending style of the text, or `None` if the text has no line endings. This is
+  /// independent of the source's line terminators.
+  pub fn line_ending(&self) -> Option<LineEnding> {
+    self.line_endings.line_ending()
+  }
+
+  /// The byte-order mark that was stripped from the start of the file when it was decoded, if
+  /// any. Text given to `Source::new` is kept as is, so its byte-order mark is `None`.
+  pub fn bom(&self) -> Option<ByteOrderMark> {
+    self.bom
+  }
+
+  /// The encoding the text was decoded from. (See `Source::decode`.)
+  pub fn text_encoding(&self) -> TextEncoding {
+    self.encoding
+  }
+
+  /// The byte offset into the original file, including any byte-order mark, of the character at
+  /// `index`. Edits made with `self.apply_edit(..)` are not reflected in the map, so `index` is
+  /// an index into the text as it was created.
+  pub fn original_offset(&self, index: ByteIndex) -> usize {
+    self.offsets.original_offset(index)
+  }
+
+  /// The text encoded as the original file was, including its byte-order mark. An error of kind
+  /// `InvalidData` is returned if the text cannot be represented in the original encoding.
+  pub fn encoded_bytes(&self) -> io::Result<Vec<u8>> {
+    let bytes = self.text().as_bytes();
+    let mut encoded = self.bom.map_or(Vec::new(), |bom| bom.as_bytes().to_vec());
+
+    match self.encoding {
+      TextEncoding::Utf8 => encoded.extend_from_slice(bytes),
+      encoding => {
+        let text = std::str::from_utf8(bytes).map_err(invalid_data)?;
+        encoded.extend(encoding.encode(text)?);
+      }
     }
+
+    Ok(encoded)
   }
 
 
-  /// Get a copy of the source (typically a slice).
-  pub fn text(&self) -> &str {
-    self.text
+  /// Gives the slice of the source text that `span` refers to. It is the caller's
+  /// responsibility to ensure `span` is a span into this source.
+  pub fn fragment(&self, span: &Span<'n, '_, T>) -> &T::Slice {
+    self.text().subslice(span.start().into()..span.end().into())
+  }
+
+
+  /// The entire source text as a slice.
+  pub fn text(&self) -> &T::Slice {
+    self.text.as_slice()
   }
 
 
@@ -116,17 +270,13 @@ impl<'n, 't> Source<'n, 't> {
   }
 
   /// Given a `byte_index: ByteIndex`, returns the `LineIndex` of the line in which `byte_index`
-  /// exists. If `byte_index` is more than one past the end, or if `byte_index` is one past the
-  /// end and the file does not end with a newline, we return `None`, as there is no such line.
-  pub fn line_index(&self, byte_index: ByteIndex) -> Result<LineIndex, LocationError> {
-    let text_len: ByteIndex = self.text.as_bytes().len().into();
-
-    if (text_len == 0usize.into())
-        || (byte_index > text_len)
-        || (
-            (byte_index == text_len)
-            & (self.text.as_bytes()[Into::<usize>::into(text_len) - 1] != b'\n')
-        ) {
+  /// exists. The index one past the end of the text is on the last line, so that the end of a
+  /// span is always located. If `byte_index` is more than one past the end, an error is returned,
+  /// as there is no such line.
+  pub fn line_index(&self, byte_index: ByteIndex) -> Result<LineIndex, LocationError<'n, '_, T>> {
+    let text_len: ByteIndex = self.len().into();
+
+    if byte_index > text_len {
       Err(
           LocationError::OutOfBounds {
           given: byte_index,
@@ -138,13 +288,13 @@ impl<'n, 't> Source<'n, 't> {
       match self.line_starts.binary_search(&byte_index) {
 
         // `byte_index` is the start of a line.
-        Ok(line) => LineIndex::from(line as u32),
+        Ok(line) => LineIndex::new(line),
 
         // `byte_index` is not itself the start of a line, but `Err` contains "the
         // index where a matching element could be inserted while maintaining
         // sorted order," which is the start of the line following `byte_index`.
         // Thus, the line containing `byte_index` must be `next_line` - 1.
-        Err(next_line) => LineIndex::from(next_line as u32 - 1),
+        Err(next_line) => LineIndex::new(next_line - 1),
 
       };
 
@@ -156,7 +306,7 @@ impl<'n, 't> Source<'n, 't> {
   /// number of bytes between line start and position defined by idx.
   /// Call with span.start() to use with Span. If `idx` refers to a position
   /// past the end of the file, it returns an error.
-  pub fn location_in_bytes(&self, idx: ByteIndex) -> Result<Location, LocationError> {
+  pub fn location_in_bytes(&self, idx: ByteIndex) -> Result<Location, LocationError<'n, '_, T>> {
     let line = self.line_index(idx)?;
     // If `self.line_index(idx)` succeeds, `self.line_start(..)` is guaranteed to succeed, so the
     // (outer) unwrap is safe.
@@ -174,7 +324,7 @@ impl<'n, 't> Source<'n, 't> {
   /// Gives the (row, column) location of `idx` where column is the count of UTF-8 chars between
   /// line start and position defined by idx. Call with span.start() to use with Span. An error is
   /// returned if `idx` refers to a position past the end of the file. (See `self.line_index(..)`.)
-  pub fn location_utf8(&self, idx: ByteIndex) -> Result<Location, LocationError> {
+  pub fn location_utf8(&self, idx: ByteIndex) -> Result<Location, LocationError<'n, '_, T>> {
     let location_in_bytes = self.location_in_bytes(idx)?;
     let start_of_line = (idx.0 - location_in_bytes.column_index.0) as usize;
 
@@ -182,7 +332,7 @@ impl<'n, 't> Source<'n, 't> {
     let column: ColumnIndex =
       (
         num_chars(
-          &self.text.as_bytes()[
+          &self.text().as_bytes()[
               start_of_line .. idx.0 as usize
           ]
         )
@@ -197,7 +347,7 @@ impl<'n, 't> Source<'n, 't> {
   }
 
   /// Same as location_utf8(..), but uses a fast naive method of counting UTF-8 characters.
-  pub fn location_naive_utf8(&self, idx: ByteIndex) -> Result<Location, LocationError> {
+  pub fn location_naive_utf8(&self, idx: ByteIndex) -> Result<Location, LocationError<'n, '_, T>> {
     let location_in_bytes = self.location_in_bytes(idx)?;
     // The offset in bytes of the start of the line `idx` lives on
     let start_of_line = (idx.0 - location_in_bytes.column_index.0) as usize;
@@ -205,10 +355,10 @@ impl<'n, 't> Source<'n, 't> {
     let column: ColumnIndex =
         (
           naive_num_chars(
-            &self.text.as_bytes()[
+            &self.text().as_bytes()[
                 start_of_line .. idx.0 as usize
                 ]
-          ) + 1
+          )
         ).into();
 
     Ok(
@@ -220,7 +370,118 @@ impl<'n, 't> Source<'n, 't> {
   }
 
 
-  pub fn line_span(&self, line_index: LineIndex) -> Result<Span, LineIndexOutOfBoundsError> {
+  /// Gives the (row, column) location of `idx` where column is the count of UTF-16 code units
+  /// between line start and position defined by idx, as the Language Server Protocol expects by
+  /// default. An error is returned if `idx` refers to a position past the end of the file.
+  pub fn location_utf16(&self, idx: ByteIndex) -> Result<Location, LocationError<'n, '_, T>> {
+    self.location_in(idx, PositionEncoding::Utf16)
+  }
+
+  /// Gives the (row, column) location of `idx` with the column counted in `encoding`.
+  pub fn location_in(&self, idx: ByteIndex, encoding: PositionEncoding)
+    -> Result<Location, LocationError<'n, '_, T>>
+  {
+    let location_in_bytes = self.location_in_bytes(idx)?;
+    let start_of_line = (idx.0 - location_in_bytes.column_index.0) as usize;
+    let column = encoding.columns(&self.text().as_bytes()[start_of_line .. idx.0 as usize]);
+
+    Ok(
+      Location{
+        line_index: location_in_bytes.line_index,
+        column_index: column.into()
+      }
+    )
+  }
+
+  /// The inverse of `self.location_utf16(..)`: gives the `ByteIndex` of the position `column`
+  /// UTF-16 code units into the line with index `line`. An error is returned if `line` does
+  /// not exist, if `column` is past the end of the line, or if `column` falls between the two
+  /// halves of a surrogate pair.
+  pub fn byte_index_from_utf16(&self, line: LineIndex, column: ColumnIndex)
+    -> Result<ByteIndex, LocationError<'n, '_, T>>
+  {
+    self.byte_index(Location::new(line, column), PositionEncoding::Utf16)
+  }
+
+  /// The inverse of `self.location_in(..)`: gives the `ByteIndex` of `location`, whose column is
+  /// counted in `encoding`. The column may point one past the last character of the line, that
+  /// is, at the line's newline or at the end of the text. An error is returned if the line does
+  /// not exist (`LineOutOfBounds`), if the column is past the end of the line
+  /// (`ColumnOutOfBounds`), or if the column falls inside of a multi-byte character, surrogate
+  /// pair, or multi-column grapheme cluster (`InvalidCharBoundary`, giving the index of the
+  /// start of the character).
+  pub fn byte_index(&self, location: Location, encoding: PositionEncoding)
+    -> Result<ByteIndex, LocationError<'n, '_, T>>
+  {
+    let Location { line_index: line, column_index: column } = location;
+    let line_start = self.line_start(line)?;
+    let bytes      = self.line_span_without_terminator(line)?.fragment().as_bytes();
+    let target  = column.0 as usize;
+
+    let mut columns = 0;
+    let mut offset  = 0;
+    for unit in encoding.units(bytes) {
+      if columns >= target {
+        break;
+      }
+      if unit.column > target {
+        return Err(
+          LocationError::InvalidCharBoundary {
+            given: line_start + ByteOffset::new(unit.bytes.start)
+          }
+        );
+      }
+      columns = unit.column;
+      offset  = unit.bytes.end;
+    }
+
+    if columns < target {
+      return Err(
+        LocationError::ColumnOutOfBounds {
+          given: column,
+          max: columns.into()
+        }
+      );
+    }
+
+    Ok(line_start + ByteOffset::new(offset))
+  }
+
+  /// Gives the column of `idx` as an editor displays it: tabs are expanded to the source's tab
+  /// width, and every other character takes one column. (See `PositionEncoding::DisplayWidth`
+  /// for columns that also account for wide characters.)
+  pub fn visual_column(&self, idx: ByteIndex) -> Result<ColumnIndex, LocationError<'n, '_, T>> {
+    Ok(self.location_in(idx, self.visual_encoding())?.column_index)
+  }
+
+  /// The inverse of `self.visual_column(..)`: gives the `ByteIndex` of the character at visual
+  /// column `column` of the line with index `line`. A column that falls inside of the expansion
+  /// of a tab gives `LocationError::InvalidCharBoundary` with the index of the tab.
+  pub fn byte_index_from_visual_column(&self, line: LineIndex, column: ColumnIndex)
+    -> Result<ByteIndex, LocationError<'n, '_, T>>
+  {
+    self.byte_index(Location::new(line, column), self.visual_encoding())
+  }
+
+  fn visual_encoding(&self) -> PositionEncoding {
+    PositionEncoding::Visual { tab_width: self.tab_width }
+  }
+
+  /// Gives the span between the locations `start` and `end`, whose columns are counted in
+  /// `encoding`. The locations may be given in either order. (See `self.byte_index(..)`.)
+  pub fn location_span(&self, start: Location, end: Location, encoding: PositionEncoding)
+    -> Result<Span<'n, '_, T>, LocationError<'n, '_, T>>
+  {
+    let start = self.byte_index(start, encoding)?;
+    let end   = self.byte_index(end, encoding)?;
+
+    Ok(Span::new(start.min(end), start.max(end) - start.min(end), self))
+  }
+
+
+  pub fn line_span(&self, line_index: LineIndex)
+    -> Result<Span<'n, '_, T>, LineIndexOutOfBoundsError>
+  {
     let line_start = self.line_start(line_index)?;
     let next_line_start = self.line_start(line_index + LineOffset::new(1))?;
 
@@ -234,6 +495,33 @@ impl<'n, 't> Source<'n, 't> {
   }
 
 
+  /// Iterates over the lines of the text, giving the index and span of each line without its
+  /// terminator. A text ending with a line terminator has no empty line after it, and an empty
+  /// text has a single empty line. (See `Span::lines`.)
+  pub fn lines(&self) -> Lines<'n, '_, T> {
+    self.source_span().lines()
+  }
+
+  /// The span of the line with index `line_index` without its line terminator. Unlike
+  /// `self.line_span(..)`, the last line, which has no terminator, is also accepted. A `\r\n`
+  /// is always treated as a terminator, even if only `\n` ends lines.
+  pub fn line_span_without_terminator(&self, line_index: LineIndex)
+    -> Result<Span<'n, '_, T>, LineIndexOutOfBoundsError>
+  {
+    let line_start = self.line_start(line_index)?;
+    let line_end =
+        match self.line_start(line_index + LineOffset::new(1)) {
+          Ok(next_line_start) => {
+            let line = &self.text().as_bytes()[line_start.0 as usize .. next_line_start.0 as usize];
+            next_line_start - ByteOffset::new(self.line_terminators.terminator_length(line))
+          }
+          Err(_) => self.end(),
+        };
+
+    Ok(Span::new(line_start, line_end - line_start, self))
+  }
+
+
   /// Returns the `ByteIndex` to the start of line number `line_index`, where `line_index`
   /// starts at $0$. If the source text ends in a newline, the `ByteIndex` of the
   /// "line"  with `line_index` will be one past the end. This method can be thought of as a
@@ -258,21 +546,22 @@ impl<'n, 't> Source<'n, 't> {
 
   /// Create a new Nom nature `LocatedSpan` (`LSpan` here) from this `Source`'s text.
   #[cfg(feature = "nom-parsing")]
-  pub fn source_located_span(&'t self) -> LSpan<'n, 't> {
+  pub fn source_located_span(&self) -> LSpan<'n, '_, T> {
     LSpan::new_extra(
-      self.text,
+      self.text(),
       self,
     )
   }
 
 
   /// Convert a `Span` to Nom's native `LocatedSpan` (`LSpan` here)
-  #[cfg(any(feature = "nom-parsing", feature = "reporting"))]
-  pub fn span_to_located(&'t self, span: &'t Span) -> LSpan<'n, 't> {
+  #[cfg(feature = "nom-parsing")]
+  pub fn span_to_located<'t>(&'t self, span: &Span<'n, 't, T>) -> LSpan<'n, 't, T> {
     unsafe {
-      LSpan::<'n, 't>::new_from_raw_offset(
+      LSpan::<'n, 't, T>::new_from_raw_offset(
         span.start().into(),
-        self.line_index(span.start()).unwrap().into(),
+        u32::try_from(usize::from(self.line_index(span.start()).unwrap()) + 1)
+            .expect("line number too large for nom_locate"),
         span.fragment(),
         self
       )
@@ -280,17 +569,22 @@ impl<'n, 't> Source<'n, 't> {
   }
 
 
-  pub fn name(&self) -> &'n str {
+  pub fn name(&self) -> &str {
     &self.name
   }
 
   pub const fn start(&self) -> ByteIndex{
-    ByteIndex(0u32)
+    ByteIndex(0)
   }
 
   /// The length of the text in bytes.
   pub fn len(&self) -> usize {
-    self.text.as_bytes().len()
+    self.text().byte_len()
+  }
+
+  /// Whether the source text is empty.
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
   }
 
   pub fn end(&self) -> ByteIndex{
@@ -303,29 +597,13 @@ impl<'n, 't> Source<'n, 't> {
   ///
   /// Note: `Source` does not implement Nom's `Slice` trait, as that trait requires the return
   /// type to be `Self`. We want `slice` to return a `Span`, not a `Source`.
-  pub fn slice<RangeType>(&self, range: RangeType) -> Span
+  pub fn slice<RangeType>(&self, range: RangeType) -> Span<'n, '_, T>
     where RangeType : RangeBounds<usize>
   {
-    let range_start =
-        match range.start_bound() {
-          Bound::Included(s) => { *s }
-          Bound::Excluded(s) => { *s + 1 }
-          Bound::Unbounded => { 0 }
-        };
-    let range_end =
-        match range.end_bound() {
-          Bound::Included(s) => { *s }
-          Bound::Excluded(s) => { *s - 1 }
-          Bound::Unbounded => { self.len() }
-        };
-
-    let start: ByteIndex = max(self.start().into(), range_start).into();
-    let length: ByteOffset = max(0, min(self.len().into(), range_end - range_start)).into();
-
-    Span::new(start, length, self)
+    self.source_span().slice(range)
   }
 
-  pub fn source_span(&self) -> Span {
+  pub fn source_span(&self) -> Span<'n, '_, T> {
     Span::new(
       ByteIndex::default(),
       self.len(),
@@ -333,33 +611,137 @@ impl<'n, 't> Source<'n, 't> {
     )
   }
 
-  /// Returns the `Location` (row+col) of the given `ByteIndex`.
-  pub fn location(&self, byte_index: ByteIndex) -> Result<Location, LocationError> {
-    let line_index = self.line_index(byte_index)?;
+  /// Returns the `Location` (row+col) of the given `ByteIndex`. The column is counted in the
+  /// source's position encoding, which is characters unless set otherwise. (See
+  /// `self.set_position_encoding(..)`.)
+  pub fn location(&self, byte_index: ByteIndex) -> Result<Location, LocationError<'n, '_, T>> {
+    self.location_in(byte_index, self.position_encoding)
+  }
 
-    // This `unwrap` is ok, because `line_index` is guaranteed to be an existing line.
-    let line_start_index = self.line_start(line_index).unwrap();
-    let line_src: &str = &self.text[line_start_index.into()..=byte_index.into()];
+}
 
-    Ok(
-        Location {
-        line_index: line_index,
-        column_index: ColumnIndex::from(line_src.chars().count() as u32),
+
+impl<'n> Source<'n, String> {
+
+  /// Decodes the contents of a file into a new source. The encoding is given by the file's
+  /// byte-order mark if it has one, and is `encoding` otherwise. The byte-order mark and the
+  /// encoding are remembered. (See `self.encoded_bytes()` and `self.original_offset(..)`.)
+  pub fn decode<N: Into<Cow<'n, str>>>(name: N, bytes: &[u8], encoding: TextEncoding)
+    -> io::Result<Self>
+  {
+    let bom      = ByteOrderMark::detect(bytes);
+    let encoding = bom.map_or(encoding, ByteOrderMark::encoding);
+    let prefix   = bom.map_or(0, |bom| bom.as_bytes().len());
+    let (text, offsets) = encoding.decode(&bytes[prefix..])?;
+
+    let mut source = Source::try_new(name, text).map_err(invalid_data)?;
+    source.bom      = bom;
+    source.encoding = encoding;
+    source.offsets  = offsets.with_prefix(prefix);
+    Ok(source)
+  }
+
+}
+
+
+impl<'n, T: EditableText> Source<'n, T> {
+
+  /// Replaces the text in the byte range `range` with `new_text`. Only the part of the line index
+  /// following the start of the edit is updated. The returned `Edit` can be used to carry
+  /// `IdSpan`s and `ByteIndex`es into the old text over to the new text.
+  ///
+  /// An error is returned if `range` extends past the end of the text, if, for string text,
+  /// either end of `range` is not on a `char` boundary, or if the edited text would be too long
+  /// to be indexed by a `RawIndex`.
+  pub fn apply_edit<R>(&mut self, range: R, new_text: &T::Slice)
+    -> Result<Edit, LocationError<'n, '_, T>>
+    where R: RangeBounds<usize>
+  {
+    let start =
+        match range.start_bound() {
+          Bound::Included(s) => *s,
+          Bound::Excluded(s) => s + 1,
+          Bound::Unbounded => 0,
+        };
+    let end =
+        match range.end_bound() {
+          Bound::Included(s) => s + 1,
+          Bound::Excluded(s) => *s,
+          Bound::Unbounded => self.len(),
+        };
+
+    if end > self.len() || start > end {
+      return Err(
+        LocationError::OutOfBounds {
+          given: ByteIndex::new(end.max(start)),
+          source: self
+        }
+      );
+    }
+    for index in [start, end] {
+      if !self.text().is_boundary(index) {
+        return Err(LocationError::InvalidCharBoundary { given: ByteIndex::new(index) });
       }
-    )
+    }
+    let new_length = (self.len() - (end - start)).saturating_add(new_text.byte_len());
+    if try_raw_index(new_length).is_err() {
+      return Err(LocationError::IndexOverflow { given: new_length });
+    }
+
+    let edit    = Edit::new(ByteIndex::new(start)..ByteIndex::new(end), new_text.byte_len());
+    let new_end = start + new_text.byte_len();
+
+    // Whether an index is a line start depends on at most the three bytes before it and the byte
+    // at it, so only line starts from `start` to three bytes past the replacement can change.
+    // Those are rescanned from the start of the line containing `start`, which is never in the
+    // middle of a terminator. Everything after that window moves by `edit.delta()`.
+    const WINDOW: usize = 3;
+    let first_removed = self.line_starts.partition_point(|&i| i < edit.start).max(1);
+    let first_kept    = self.line_starts.partition_point(|&i| usize::from(i) < end + WINDOW);
+    let scan_from     = usize::from(self.line_starts[first_removed - 1]);
+
+    let old_bytes = self.text().as_bytes();
+    let old_line_endings =
+        LineEndingCounts::scan(old_bytes, scan_from..old_bytes.len().min(end + WINDOW));
+    self.text.replace_range(start..end, new_text);
+    let new_bytes = self.text.as_slice().as_bytes();
+    let new_line_endings =
+        LineEndingCounts::scan(new_bytes, scan_from..new_bytes.len().min(new_end + WINDOW));
+    self.line_endings.replace(old_line_endings, new_line_endings);
+
+    let inserted: Vec<ByteIndex> =
+      self.line_terminators
+        .line_starts(new_bytes, scan_from)
+        .take_while(|&i| i < new_end + WINDOW)
+        .filter(|&i| i >= start)
+        .map(ByteIndex::new)
+        .collect();
+    let first_moved = first_removed + inserted.len();
+    self.line_starts.splice(first_removed..first_kept, inserted);
+
+    for line_start in &mut self.line_starts[first_moved..] {
+      *line_start += edit.delta();
+    }
+
+    Ok(edit)
   }
 
 }
 
 
-impl Display for Source<'_, '_> {
+impl<T: SourceText> Display for Source<'_, T> {
   fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-    write!(f, "Source {{ name: \"{}\", text: \"{}\" }}", clip(self.name, 20), clip(self.text, 20))
+    write!(
+      f,
+      "Source {{ name: \"{}\", text: \"{}\" }}",
+      clip(&self.name, 20),
+      clip(&self.text().to_str_lossy(), 20)
+    )
   }
 }
 
 // Reuses `Display::fmt()`
-impl Debug for Source<'_, '_> {
+impl<T: SourceText> Debug for Source<'_, T> {
   fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
     Display::fmt(self, f)
   }
@@ -367,81 +749,114 @@ impl Debug for Source<'_, '_> {
 
 /**
   `Files` is a trait from `codespan-reporting` and is required if `Span` is to be used with
-  `codespan-reporting`.
+  `codespan-reporting`. The source text is given to `codespan-reporting` as a `str`, which must
+  have the same byte offsets as the text, so a byte slice source that is not valid UTF-8 gives
+  an I/O error of kind `InvalidData`. (`Diagnostic::render` reports on such sources.) Column
+  numbers are counted in the source's `PositionEncoding`, so they agree with `Source::location`.
 */
 #[cfg(feature = "reporting")]
 #[allow(unused_variables)]
-impl<'n: 't, 't> Files<'t> for Source<'n, 't> {
+impl<'a, 'n: 'a, T: SourceText + 'a> Files<'a> for Source<'n, T> {
   /// A unique identifier for files in the file provider. This will be used
   /// for rendering `diagnostic::Label`s in the corresponding source files.
   type FileId = ();
   /// The user-facing name of a file, to be displayed in diagnostics.
-  type Name = &'n str;
+  type Name = &'a str;
   /// The source code of a file.
-  type Source = &'t str;
+  type Source = Cow<'a, str>;
 
   /// The user-facing name of a file.
-  // #[allow(unused_variables)]
-  fn name(&self, id: Self::FileId) -> Result<Self::Name, CodespanError> {
-    Ok(self.name)
+  fn name(&'a self, id: Self::FileId) -> Result<Self::Name, CodespanError> {
+    Ok(&self.name)
   }
 
-  /// The source code of a file.
-  // #[allow(unused_variables)]
-  fn source(&'t self, id: Self::FileId) -> Result<Self::Source, CodespanError> {
-    Ok(self.text())
+  /// The source code of a file, or an error if it is not valid UTF-8.
+  fn source(&'a self, id: Self::FileId) -> Result<Self::Source, CodespanError> {
+    let text = std::str::from_utf8(self.text().as_bytes()).map_err(invalid_data)?;
+    Ok(Cow::Borrowed(text))
   }
 
   /// The index of the line at the given byte index.
-  // #[allow(unused_variables)]
   fn line_index(&self, id: Self::FileId, byte_index: usize) -> Result<usize, CodespanError> {
-    match self.line_index(byte_index.into()){
-      Ok(v) => Ok(v.into()),
-      Err(e) => Err(e.into())
+    let byte_index =
+        ByteIndex::try_new(byte_index)
+            .map_err(|_| CodespanError::IndexTooLarge { given: byte_index, max: self.len() })?;
+
+    Ok(self.line_index(byte_index)?.into())
+  }
+
+  /// The user-facing line number at the given line index.
+  fn line_number(&self, id: Self::FileId, line_index: usize) -> Result<usize, CodespanError> {
+    self.check_line_index(line_index)?;
+    Ok(line_index + 1)
+  }
+
+  /// The user-facing column number at the given line index and byte index, counted in the
+  /// source's `PositionEncoding`. A byte index past the end of the line gives the column following
+  /// the line, including its terminator.
+  fn column_number(&self, id: Self::FileId, line_index: usize, byte_index: usize)
+    -> Result<usize, CodespanError>
+  {
+    let line  = Files::line_range(self, (), line_index)?;
+    let end   = byte_index.clamp(line.start, line.end);
+    let text  = self.text();
+    if !text.is_boundary(end) {
+      return Err(CodespanError::InvalidCharBoundary { given: byte_index });
     }
 
+    Ok(self.position_encoding.columns(&text.as_bytes()[line.start..end]) + 1)
   }
 
-  /// The byte range of line in the source of the file.
+  /// The byte range of line in the source of the file, including its terminator. The line
+  /// following the last line terminator is the last line, and ends at the end of the text.
   fn line_range(&self, id: Self::FileId, line_index: usize) -> Result<Range<usize>, CodespanError> {
-    if line_index >= self.line_starts.len() {
-      return Err(
-          CodespanError::IndexTooLarge{
-            given: line_index,
-            max: self.last_line_index().into()
-          }
-        );
-    }
+    self.check_line_index(line_index)?;
 
-    // Recall self.line_starts gives line numbers which start at 1.
-    let start = self.line_starts[line_index];
-    if  start ==( self.line_starts.len() - 1usize).into() {
-      return Ok(start.into()..start.into());
-    }
+    let start = self.line_starts[line_index].into();
+    let end   =
+        match self.line_starts.get(line_index + 1) {
+          Some(&next_line_start) => next_line_start.into(),
+          None => self.len(),
+        };
 
-    Ok(start.into()..self.line_starts[line_index + 1].into())
+    Ok(start..end)
+  }
+}
+
+#[cfg(feature = "reporting")]
+impl<T: SourceText> Source<'_, T> {
+  /// Gives `CodespanError::LineTooLarge` if there is no line with index `line_index`.
+  fn check_line_index(&self, line_index: usize) -> Result<(), CodespanError> {
+    let max = usize::from(self.last_line_index());
+    match line_index > max {
+      true => Err(CodespanError::LineTooLarge { given: line_index, max }),
+      false => Ok(()),
+    }
   }
 }
 
 
-/// Produces a list containing 0 followed by the index of the byte following every instance of
-/// `b'\n'`. These are the indices of the beginning of every line. Note that if the file ends
-/// with a newline, then the last index in this list will be one past the end of the text.
-// NOTE: this is copied from `codespan_reporting::files::line_starts` and should be kept in sync.
-fn line_starts<'s>(source: &'s [u8]) -> impl 's + Iterator<Item=usize>
-{
-  std::iter::once(0).chain(Memchr::new(b'\n', source).map(|i| i + 1))
+/// Produces a list containing 0 followed by the index of the byte following every line terminator
+/// in `text`, as given by `line_terminators`: only `\n` for `LineTerminators::Lf`, and also a lone
+/// `\r` (a CRLF ends one line, after its `\n`), U+2028, and U+2029 for `LineTerminators::All`.
+/// These are the indices of the beginning of every line. If the text ends with a terminator, the
+/// last index in this list is one past the end of the text.
+fn line_starts(text: &[u8], line_terminators: LineTerminators) -> Vec<ByteIndex> {
+  std::iter::once(0)
+    .chain(line_terminators.line_starts(text, 0))
+    .map(ByteIndex::new)
+    .collect()
 }
 
 
 
 /// A utility function that clips `text` if necessary so that the result does not exceed
-/// length `n`. It does so by replacing a sufficient amount of the middle of the string with
-/// a single "…" to make a new string of the form "prefix…postfix". If `n` is less than 2 and
-/// `text.len() > 2`, this function makes no sense, and so the original string is returned
-/// unclipped.
-fn clip(text: &str, n: usize) -> String {
-  let text_len = text.len();
+/// length `n` (in `char`s). It does so by replacing a sufficient amount of the middle of the
+/// string with a single "…" to make a new string of the form "prefix…postfix". If `n` is less
+/// than 2 and `text.len() > 2`, this function makes no sense, and so the original string is
+/// returned unclipped.
+pub(crate) fn clip(text: &str, n: usize) -> String {
+  let text_len = text.chars().count();
 
   if text_len <= n || n < 2 {
     return text.to_string();
@@ -450,10 +865,8 @@ fn clip(text: &str, n: usize) -> String {
   // The length of the prefix and postfix of the clipped string. If `n`
   // is even, we give the prefix one more character than the postfix.
   let half_n = n/2;
+  let prefix: String = text.chars().take(half_n).collect();
+  let postfix: String = text.chars().skip(text_len - half_n + ((n+1)%2)).collect();
 
-  format!(
-    "{}…{}",
-    &text[0..half_n],
-    &text[text_len - half_n + ((n+1)%2) .. text_len]
-  )
+  format!("{}…{}", prefix, postfix)
 }
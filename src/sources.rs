@@ -1,7 +1,37 @@
+use std::{
+  borrow::Cow,
+  collections::{HashMap, HashSet},
+  fmt,
+  fs,
+  io,
+  path::{Path, PathBuf},
+};
+
+#[cfg(feature = "serialization")]
+use serde::{Deserialize, Serialize};
+
 use crate::{
+  compact_span::SpanInterner,
+  encoding::invalid_data,
+  index_types::try_raw_index,
   ByteIndex,
+  CompactSpan,
+  Expansion,
+  ExpansionError,
+  Expansions,
+  FromFileContents,
+  IdSpan,
+  IndexOverflowError,
+  LoadError,
+  Location,
   LocationError,
-  Source
+  NotASourceError,
+  RawIndex,
+  Source,
+  SourceText,
+  Span,
+  TextEncoding,
+  TextSlice,
 };
 
 #[cfg(feature = "reporting")]
@@ -11,120 +41,527 @@ use codespan_reporting::files::{
 };
 
 
+/// A handle to a `Source` in a `Sources` database. A `SourceId` is `Copy` and does not borrow the
+/// database, so it can be held while more sources are added.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub struct SourceId(pub RawIndex);
+
+impl SourceId {
+  /// Panics if `n` is larger than `RawIndex::MAX`. (See `Self::try_new`.)
+  #[inline(always)]
+  pub fn new(n: usize) -> Self {
+    SourceId::try_new(n).unwrap_or_else(|error| panic!("{}", error))
+  }
+
+  /// Gives an error instead of panicking if `n` is larger than `RawIndex::MAX`.
+  #[inline(always)]
+  pub fn try_new(n: usize) -> Result<Self, IndexOverflowError> {
+    try_raw_index(n).map(SourceId)
+  }
+}
+
+impl From<SourceId> for usize {
+  #[inline]
+  fn from(id: SourceId) -> usize {
+    id.0 as usize
+  }
+}
+
+impl fmt::Display for SourceId {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    self.0.fmt(f)
+  }
+}
+
+impl fmt::Debug for SourceId {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "SourceId(")?;
+    self.0.fmt(f)?;
+    write!(f, ")")
+  }
+}
+
+
+/// An edge in the include graph of a `Sources` database: the source `source` was included by the
+/// include directive spanned by `directive`, which lies in the parent source.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub struct Include {
+  pub source   : SourceId,
+  pub directive: IdSpan,
+}
+
+impl Include {
+  /// The source containing the include directive.
+  pub fn parent(&self) -> SourceId {
+    self.directive.source_id()
+  }
+}
+
+
 /**
 A database of source files.
 
-The `SourceType` generic parameter determines how source text is stored. Using [`String`] will have
+The `T` generic parameter determines how source text is stored. Using [`String`] will have
 `Sources` take ownership of all source text. Smart pointer types such as [`Cow<'_, str>`],
 [`Rc<str>`] or [`Arc<str>`] can be used to share the source text with the rest of the program.
+Likewise, source names may be borrowed (`&'n str`) or owned (`String`).
+
+Adding a source gives a `SourceId`. Because an `IdSpan` refers to its source by `SourceId` rather
+than by reference, `IdSpan`s into many sources can be held while more sources are added. Use
+`Sources::span` to turn an `IdSpan` back into a `Span`.
+
+```
+use saucepan::Sources;
+
+let mut sources: Sources<'static, String> = Sources::new();
+// Text read at runtime is owned by the database.
+let first  = sources.add("first.x", "let x = 42;".to_string());
+let number = sources.get(first).unwrap().slice(8..10).id_span().unwrap();
+
+// Adding another source does not invalidate `number`.
+let second = sources.add(format!("{}.x", "second"), "let y = x;".to_string());
+
+assert_eq!(sources.name(second).unwrap(), "second.x");
+assert_eq!(sources.fragment(number).unwrap(), "42");
+assert_eq!(sources.location(number).unwrap().column_number().0, 9);
+```
+
+Sources can also be loaded directly from the filesystem with `Sources::load_file` and
+`Sources::load_dir`, in which case the canonical path of the file is its name, and each file is
+only loaded once.
+
+When one source "includes" another, record the include directive with `Sources::add_include`. The
+include graph can then be queried, for example to report where a source was included from:
+
+```
+use saucepan::Sources;
+
+let mut sources = Sources::new();
+let main   = sources.add("main.x", "#include \"util.x\"\nmain();\n");
+let util   = sources.add("util.x", "#include \"base.x\"\n");
+let base   = sources.add("base.x", "oops\n");
+
+let directive = sources.get(main).unwrap().slice(0..18).id_span().unwrap();
+sources.add_include(util, directive).unwrap();
+let directive = sources.get(util).unwrap().slice(0..18).id_span().unwrap();
+sources.add_include(base, directive).unwrap();
+
+let trace: Vec<String> =
+    sources.include_chain(base)
+           .into_iter()
+           .map(|span| {
+             let location = sources.location(span).unwrap();
+             format!(
+               "included from {}:{}:{}",
+               sources.name(span.source_id()).unwrap(),
+               location.line_number(),
+               location.column_number()
+             )
+           })
+           .collect();
+
+assert_eq!(trace, vec!["included from util.x:1:1", "included from main.x:1:1"]);
+assert_eq!(sources.dependencies(main), vec![util, base]);
+```
 
 [`Cow<'_, str>`]: std::borrow::Cow
 [`Rc<str>`]: std::rc::Rc
 [`Arc<str>`]: std::sync::Arc
 */
 #[derive(Clone, Debug)]
-pub struct Sources<'n, 't>
+pub struct Sources<'n, T: SourceText>
 {
-  sources: Vec<Source<'n, 't>>,
+  sources: Vec<Source<'n, T>>,
+  /// The sources loaded from the filesystem, keyed by canonical path.
+  paths: HashMap<PathBuf, SourceId>,
+  /// The edges of the include graph in the order they were added.
+  includes: Vec<Include>,
+  /// The spans too large to be stored inline in a `CompactSpan`.
+  interner: SpanInterner,
 }
 
-impl<'n, 't> Default for Sources<'n, 't>
+impl<'n, T: SourceText> Default for Sources<'n, T>
 {
   fn default() -> Self {
-    Self { sources: vec![] }
+    Self {
+      sources: vec![],
+      paths: HashMap::new(),
+      includes: vec![],
+      interner: SpanInterner::default(),
+    }
   }
 }
 
 
-impl<'n, 't> Sources<'n, 't> {
+impl<'n, T: SourceText> Sources<'n, T> {
 
   pub fn new() -> Self {
-    Sources::<'n, 't>::default()
+    Sources::<'n, T>::default()
   }
 
-  /// Add a file to the database, returning a reference to the handle that can be used to refer to
-  /// it again.
-  pub fn add(&mut self, name: &'n str, text: &'t str) -> &Source<'n, 't> {
-    self.sources.push(Source::new(name, text));
-    self.sources.last().unwrap()
+  /// Add a file to the database, returning the `SourceId` that can be used to refer to it again.
+  /// Panics if the text is too long to be indexed by a `RawIndex`, or if the database already
+  /// holds as many sources as a `SourceId` can count. (See `Sources::try_add`.)
+  pub fn add<N: Into<Cow<'n, str>>>(&mut self, name: N, text: T) -> SourceId {
+    self.try_add(name, text).unwrap_or_else(|error| panic!("{}", error))
   }
 
-  pub unsafe fn get_unchecked(&self, source_id: usize) -> &Source<'n, 't> {
-    self.sources.get_unchecked(source_id)
+  /// Like `self.add(..)`, but gives an error instead of panicking if the text is longer than
+  /// `RawIndex::MAX` bytes or there is no `SourceId` left for it.
+  pub fn try_add<N: Into<Cow<'n, str>>>(&mut self, name: N, text: T)
+    -> Result<SourceId, IndexOverflowError>
+  {
+    self.push(Source::try_new(name, text)?)
   }
 
-  /// Get the source file using the file id.
-  pub fn get(&self, source_id: usize) -> Option<&Source<'n, 't>> {
-    self.sources.get(source_id)
+  /// Add a synthetic source holding the text produced by the macro expansion `expansion`. The
+  /// spans in `expansion` must refer to sources already in the database. As with
+  /// `self.try_add(..)`, an error is also returned if the text or the database is too large.
+  pub fn add_expansion<N: Into<Cow<'n, str>>>(&mut self, name: N, text: T, expansion: Expansion)
+    -> Result<SourceId, ExpansionError>
+  {
+    self.try_get(expansion.call_site.source_id())?;
+    self.try_get(expansion.definition.source_id())?;
+
+    let mut source = Source::try_new(name, text)?;
+    source.set_expansion(expansion);
+
+    Ok(self.push(source)?)
   }
 
+  /// Gives an error if there is no `SourceId` left for `source`.
+  fn push(&mut self, mut source: Source<'n, T>) -> Result<SourceId, IndexOverflowError> {
+    let id = SourceId::try_new(self.sources.len())?;
+
+    source.set_id(id);
+    self.sources.push(source);
+    Ok(id)
+  }
+
+  /// The number of sources in the database.
+  pub fn len(&self) -> usize {
+    self.sources.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.sources.is_empty()
+  }
+
+  /// Iterates over the sources in the database in the order they were added.
+  pub fn iter(&self) -> impl Iterator<Item=(SourceId, &Source<'n, T>)> {
+    self.sources
+        .iter()
+        .enumerate()
+        .map(|(i, source)| (SourceId::new(i), source))
+  }
+
+  /// Get the source file using the file id without bounds checking.
+  ///
+  /// # Safety
+  ///
+  /// `source_id` must have been returned by `self.add(..)`.
+  pub unsafe fn get_unchecked(&self, source_id: SourceId) -> &Source<'n, T> {
+    self.sources.get_unchecked(usize::from(source_id))
+  }
 
   /// Get the source file using the file id.
-  pub unsafe fn get_unchecked_mut(& mut self, source_id: usize) -> &mut Source<'n, 't> {
-    self.sources.get_unchecked_mut(source_id)
+  pub fn get(&self, source_id: SourceId) -> Option<&Source<'n, T>> {
+    self.sources.get(usize::from(source_id))
+  }
+
+
+  /// Get the source file using the file id without bounds checking.
+  ///
+  /// # Safety
+  ///
+  /// `source_id` must have been returned by `self.add(..)`.
+  pub unsafe fn get_unchecked_mut(& mut self, source_id: SourceId) -> &mut Source<'n, T> {
+    self.sources.get_unchecked_mut(usize::from(source_id))
   }
 
 
   /// Get the source file using the file id.
-  pub fn get_mut(&mut self, source_id: usize) -> Option<&mut Source<'n, 't>> {
-    self.sources.get_mut(source_id)
+  pub fn get_mut(&mut self, source_id: SourceId) -> Option<&mut Source<'n, T>> {
+    self.sources.get_mut(usize::from(source_id))
   }
-}
 
+  /// Same as `self.get(..)`, but gives an error instead of `None`.
+  fn try_get(&self, source_id: SourceId) -> Result<&Source<'n, T>, NotASourceError> {
+    self.get(source_id).ok_or(
+      NotASourceError {
+        given: source_id,
+        max: SourceId::new(self.sources.len())
+      }
+    )
+  }
 
-// It's not clear if this is useful anymore.
-#[cfg(feature = "reporting")]
-impl<'n: 't, 't> Files<'t> for Sources<'n, 't>
-{
-  type FileId = usize;
-  // Index into self.sources
-  type Name = &'n str;
-  type Source = &'t str;
-
-  fn name(&self, id: Self::FileId) -> Result<Self::Name, FileError> {
-    if id >= self.sources.len() {
-      return Err(FileError::IndexTooLarge { given: id, max: self.sources.len() });
+  /// The name of the source with the given id.
+  pub fn name(&self, source_id: SourceId) -> Result<&str, NotASourceError> {
+    Ok(self.try_get(source_id)?.name())
+  }
+
+  /// Gives the `Span` corresponding to `span`, which borrows its source from `self`. An error is
+  /// returned if the source does not exist, if `span` extends past the end of the source, or if
+  /// either end of `span` is not a boundary of the source text. (See `TextSlice::is_boundary`.)
+  pub fn span(&self, span: IdSpan) -> Result<Span<'n, '_, T>, LocationError<'n, '_, T>> {
+    let source = self.try_get(span.source_id())?;
+    let text   = source.text();
+    let start  = usize::from(span.start());
+    let end    = start.saturating_add(span.len());
+
+    if end > source.len() {
+      let given = ByteIndex::try_new(end).unwrap_or(ByteIndex(RawIndex::MAX));
+      return Err(LocationError::OutOfBounds { given, source });
     }
+    for index in [start, end] {
+      if !text.is_boundary(index) {
+        return Err(LocationError::InvalidCharBoundary { given: ByteIndex::new(index) });
+      }
+    }
+
+    Ok(Span::new(span.start(), span.len(), source))
+  }
+
+  /// Encodes `span` as a `CompactSpan`, interning it in this database if it cannot be stored
+  /// inline. The compact span can only be expanded through this database (or a clone of it).
+  pub fn compact(&self, span: IdSpan) -> CompactSpan {
+    CompactSpan::inline(span).unwrap_or_else(|| CompactSpan::interned(self.interner.intern(span)))
+  }
+
+  /// The span interned by `Sources::compact` at `index`.
+  pub(crate) fn interned_span(&self, index: u32) -> IdSpan {
+    self.interner.get(index)
+  }
+
+  /// Gives a span covering the entire source with the given id.
+  pub fn source_span(&self, source_id: SourceId) -> Result<IdSpan, NotASourceError> {
+    let source = self.try_get(source_id)?;
 
-    Ok(self.sources[id].name())
+    Ok(IdSpan::new(source.start(), source.len(), source_id))
   }
 
-  fn source(&'t self, id: Self::FileId) -> Result<Self::Source, FileError> {
-    if id >= self.sources.len() {
-      return Err(FileError::IndexTooLarge { given: id, max: self.sources.len() });
+  /// The slice of source text that `span` refers to. (See `Sources::span` for the errors.)
+  pub fn fragment(&self, span: IdSpan) -> Result<&T::Slice, LocationError<'n, '_, T>> {
+    Ok(self.span(span)?.fragment())
+  }
+
+  /// Provides the (row_index, column_index) location of the start of `span`. (See
+  /// `Span::location`.)
+  pub fn location(&self, span: IdSpan) -> Result<Location, LocationError<'n, '_, T>> {
+    self.span(span)?.location()
+  }
+
+  /// The macro expansion that produced the source with the given id, or `None` if the source is
+  /// not synthetic or does not exist.
+  pub fn expansion(&self, source_id: SourceId) -> Option<Expansion> {
+    self.get(source_id)?.expansion()
+  }
+
+  /// Walks outward through the macro expansions that produced the source with the given id,
+  /// innermost first.
+  pub fn expansions(&self, source_id: SourceId) -> Expansions<'_, 'n, T> {
+    Expansions::new(self, self.expansion(source_id))
+  }
+
+  /// The `SourceId` of the source loaded from `path`, if any. The path need not be canonical.
+  pub fn path_id<P: AsRef<Path>>(&self, path: P) -> Option<SourceId> {
+    let path = fs::canonicalize(path).ok()?;
+    self.paths.get(&path).copied()
+  }
+
+  // region Include graph
+
+  /// Records that `source` was included by the include directive spanned by `directive`. A source
+  /// may be included more than once, from the same or from different parents.
+  pub fn add_include(&mut self, source: SourceId, directive: IdSpan) -> Result<(), NotASourceError> {
+    self.try_get(source)?;
+    self.try_get(directive.source_id())?;
+
+    self.includes.push(Include { source, directive });
+    Ok(())
+  }
+
+  /// All edges of the include graph in the order they were added.
+  pub fn includes(&self) -> &[Include] {
+    &self.includes
+  }
+
+  /// The include directive by which `source` was first included, or `None` if `source` was never
+  /// included, e.g. because it is a root of the include graph.
+  pub fn included_from(&self, source: SourceId) -> Option<IdSpan> {
+    self.include_sites(source).next()
+  }
+
+  /// The include directives that include `source`, in the order they were added.
+  pub fn include_sites(&self, source: SourceId) -> impl Iterator<Item=IdSpan> + '_ {
+    self.includes
+        .iter()
+        .filter(move |include| include.source == source)
+        .map(|include| include.directive)
+  }
+
+  /// The sources directly included by `parent`, in the order they were added.
+  pub fn included_by(&self, parent: SourceId) -> impl Iterator<Item=SourceId> + '_ {
+    self.includes
+        .iter()
+        .filter(move |include| include.parent() == parent)
+        .map(|include| include.source)
+  }
+
+  /// Walks outward from `source` through the include directives by which each source was first
+  /// included, innermost first. This is the "included from ..." chain of a diagnostic. The walk
+  /// stops early if the include graph has a cycle.
+  pub fn include_chain(&self, source: SourceId) -> Vec<IdSpan> {
+    let mut chain   = Vec::new();
+    let mut visited = vec![source];
+    let mut current = source;
+
+    while let Some(directive) = self.included_from(current) {
+      chain.push(directive);
+      current = directive.source_id();
+      if visited.contains(&current) {
+        break;
+      }
+      visited.push(current);
     }
-    else {
-      // We checked `id`, so the following is infallible.
-      let source = unsafe{self.sources.get_unchecked(id)};
-      Ok(source.text())
+
+    chain
+  }
+
+  /// All sources transitively included by `root`, in depth-first order and without duplicates.
+  /// `root` itself is not included in the result unless it includes itself.
+  pub fn dependencies(&self, root: SourceId) -> Vec<SourceId> {
+    let mut dependencies = Vec::new();
+    let mut visited = HashSet::new();
+    let mut stack: Vec<SourceId> = self.included_by(root).collect();
+    stack.reverse();
+
+    while let Some(source) = stack.pop() {
+      if !visited.insert(source) {
+        continue;
+      }
+      dependencies.push(source);
+
+      let mut children: Vec<SourceId> = self.included_by(source).collect();
+      children.reverse();
+      stack.extend(children);
     }
+
+    dependencies
   }
 
-  fn line_index(&self, id: Self::FileId, byte_index: usize) -> Result<usize, FileError> {
-    if id >= self.sources.len() {
-      Err(FileError::IndexTooLarge { given: id, max: self.sources.len() })
-    } else {
-      self.sources[id]
-          .line_index(ByteIndex(byte_index as u32))
-          .map(|v| v.into())
-          .map_err(| e | {
-            match e {
-              LocationError::OutOfBounds { given, source } => FileError::IndexTooLarge {
-                given: given.into(),
-                max: source.text().as_bytes().len().into()
-              },
-              _ => FileError::FileMissing
-            }
-
-          })
+  // endregion
+}
+
+
+impl<'n, T: SourceText> Sources<'n, T> {
+  /// Reads the file at `path` into the database if it has not been loaded already, making the
+  /// source from the file's contents with `make_source`.
+  fn load_file_with<P, F>(&mut self, path: P, make_source: F) -> Result<SourceId, LoadError>
+    where P: AsRef<Path>,
+          F: FnOnce(String, Vec<u8>) -> io::Result<Source<'n, T>>
+  {
+    let path = path.as_ref();
+    let io_error = |error| LoadError::Io { path: path.to_path_buf(), error };
+
+    let canonical_path = fs::canonicalize(path).map_err(io_error)?;
+    if let Some(&id) = self.paths.get(&canonical_path) {
+      return Ok(id);
     }
+
+    let contents = fs::read(&canonical_path).map_err(io_error)?;
+    let name     = canonical_path.to_string_lossy().into_owned();
+    let source   = make_source(name, contents).map_err(io_error)?;
+    let id       = self.push(source).map_err(|error| io_error(invalid_data(error)))?;
+
+    self.paths.insert(canonical_path, id);
+    Ok(id)
   }
+}
 
-  fn line_range(&self, id: Self::FileId, line_index: usize) -> Result<std::ops::Range<usize>, FileError> {
-    if id >= self.sources.len() {
-      Err(FileError::IndexTooLarge { given: id, max: self.sources.len() })
-    } else {
-      self.sources[id].line_range((), line_index).into()
+impl<'n> Sources<'n, String> {
+  /// Like `self.load_file(..)`, but decodes the file from `encoding` unless it starts with a
+  /// byte-order mark, which takes precedence. (See `Source::decode`.)
+  pub fn load_file_decoded<P: AsRef<Path>>(&mut self, path: P, encoding: TextEncoding)
+    -> Result<SourceId, LoadError>
+  {
+    self.load_file_with(path, |name, contents| Source::decode(name, &contents, encoding))
+  }
+}
+
+impl<'n, T: FromFileContents> Sources<'n, T> {
+
+  /// Reads the file at `path` into the database, naming the source by the file's canonical path.
+  /// If the file has already been loaded, the existing `SourceId` is returned and the file is not
+  /// read again.
+  pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<SourceId, LoadError> {
+    self.load_file_with(path, |name, contents| {
+      Source::try_new(name, T::from_file_contents(contents)?).map_err(invalid_data)
+    })
+  }
+
+  /// Loads every file matching the glob `pattern` (e.g. `"src/**/*.x"`), in alphabetical order.
+  /// Directories matching the pattern are skipped. Gives the `SourceId`s of all matching files,
+  /// including those that were already loaded.
+  #[cfg(feature = "load-dir")]
+  pub fn load_dir(&mut self, pattern: &str) -> Result<Vec<SourceId>, LoadError> {
+    let mut ids = Vec::new();
+
+    for path in glob::glob(pattern).map_err(LoadError::Pattern)? {
+      let path = path?;
+      if path.is_dir() {
+        continue;
+      }
+      ids.push(self.load_file(path)?);
     }
+
+    Ok(ids)
+  }
+}
+
+
+/// Reports diagnostics spanning several sources with `codespan-reporting`, each source being
+/// identified by its `SourceId`. Every method behaves as the `Files` implementation of the source
+/// with the given id, and gives `FileError::FileMissing` if there is no such source.
+#[cfg(feature = "reporting")]
+impl<'a, 'n: 'a, T: SourceText + 'a> Files<'a> for Sources<'n, T> {
+  type FileId = SourceId;
+  type Name = &'a str;
+  type Source = Cow<'a, str>;
+
+  fn name(&'a self, id: Self::FileId) -> Result<Self::Name, FileError> {
+    Files::name(self.file(id)?, ())
+  }
+
+  fn source(&'a self, id: Self::FileId) -> Result<Self::Source, FileError> {
+    Files::source(self.file(id)?, ())
+  }
+
+  fn line_index(&self, id: Self::FileId, byte_index: usize) -> Result<usize, FileError> {
+    Files::line_index(self.file(id)?, (), byte_index)
+  }
+
+  fn line_number(&self, id: Self::FileId, line_index: usize) -> Result<usize, FileError> {
+    Files::line_number(self.file(id)?, (), line_index)
+  }
+
+  fn column_number(&self, id: Self::FileId, line_index: usize, byte_index: usize)
+    -> Result<usize, FileError>
+  {
+    Files::column_number(self.file(id)?, (), line_index, byte_index)
+  }
+
+  fn line_range(&self, id: Self::FileId, line_index: usize) -> Result<std::ops::Range<usize>, FileError> {
+    Files::line_range(self.file(id)?, (), line_index)
+  }
+}
+
+#[cfg(feature = "reporting")]
+impl<'n, T: SourceText> Sources<'n, T> {
+  /// The source with id `id`, for the `Files` implementation.
+  fn file(&self, id: SourceId) -> Result<&Source<'n, T>, FileError> {
+    self.get(id).ok_or(FileError::FileMissing)
   }
 }
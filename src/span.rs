@@ -1,21 +1,18 @@
 /*!
 The `Span` struct is the fundamental type of Saucepan that represents a location within a source
-text.
+text. A `Span` is generic over the text type of its `Source`, so the same machinery works for
+`&str` and `&[u8]` sources.
 
 
 ```
-//use nom::{bytes::complete::};
-
-use saucepan::Span;
-
-fn main()  {
-
-
-}
-
-
+use saucepan::{Slice, Source, Span};
 
+let source = Source::new("poem.txt", "Turning and turning\nin the widening gyre");
+let span: Span = source.source_span();
+let word = span.slice(20..22);
 
+assert_eq!(word.fragment(), "in");
+assert_eq!(word.location().unwrap().line_number().0, 2);
 ```
 
 */
@@ -23,40 +20,48 @@ fn main()  {
 
 
 use std::{
-  ops::{RangeBounds, Bound},
-  cmp::{min, max}
+  cmp::{min, max, Ordering},
+  hash::{Hash, Hasher},
+  ops::{Bound, Range, RangeBounds, RangeInclusive},
 };
 
-pub use std::{
-  fmt::{
-    Display,
-    Formatter,
-    Result as FmtResult
-  },
-  num::NonZeroUsize,
-  ops::{Range, RangeFrom, RangeFull, RangeTo},
-  slice,
-  slice::{Iter},
-  str::{FromStr, CharIndices, Chars},
-  iter::{Enumerate, Map},
-  convert::Into,
+pub use std::fmt::{
+  Debug,
+  Display,
+  Formatter,
 };
 
 
 #[cfg(feature = "serialization")]
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
 
 
 use crate::{
+  error::{IncompatibleSourcesError, IndexOverflowError},
+  index_types::try_raw_index,
+  CompactSpan,
+  source::clip,
+  AsBytes,
   ByteIndex,
   RawIndex,
   ByteOffset,
   Source,
+  SourceText,
+  ColumnIndex,
+  LineIndex,
   LineNumber,
+  LineOffset,
   Slice,
   LocationError,
   Location,
-  ColumnNumber
+  PositionEncoding,
+  ColumnNumber,
+  Expansion,
+  Expansions,
+  IdSpan,
+  SourceId,
+  Sources,
+  TextSlice,
 };
 
 
@@ -64,20 +69,46 @@ use crate::{
 A `Span` holds the start, length, and reference to the source of a piece of source code. A `Span`
 should not be created directly. Rather, the `Span` should be obtained from the `Source` or `Sources`
 struct that owns the text, or through a method on an exiting span.
+
+The type parameter `T` is the text type of the `Source`, `&str` by default.
+
+Two spans are equal if they have the same start and length and refer to the same `Source` object.
 */
-#[derive(Debug, Copy, Clone, Hash)]
-#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
-pub struct Span<'n, 't> {
+#[cfg_attr(feature = "serialization", derive(Serialize))]
+pub struct Span<'n, 't, T: SourceText = &'t str> {
   start     : ByteIndex,
   length    : ByteOffset,
-  pub source: &'t Source<'n, 't>
+  pub source: &'t Source<'n, T>
+}
+
+// `Clone` and `Copy` are implemented by hand so as not to require `T: Copy`.
+impl<'n, 't, T: SourceText> Clone for Span<'n, 't, T> {
+  fn clone(&self) -> Self {
+    *self
+  }
+}
+
+impl<'n, 't, T: SourceText> Copy for Span<'n, 't, T> {}
+
+impl<'n, 't, T: SourceText> Debug for Span<'n, 't, T> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("Span")
+     .field("start", &self.start)
+     .field("length", &self.length)
+     .field("source", self.source)
+     .finish()
+  }
 }
 
-impl<'n, 't> Span<'n, 't> {
+impl<'n, 't, T: SourceText> Span<'n, 't, T> {
   pub fn len(&self) -> usize {
     self.length.into()
   }
 
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
 
   /// The start represents the position of the fragment relatively to
   /// the input of the parser. It starts at start 0.
@@ -91,10 +122,10 @@ impl<'n, 't> Span<'n, 't> {
   /// Note: this will work even if the two spans are disjoint.
   /// If this doesn't make sense in your application, you should handle it yourself.
   /// In that case, you can use `Span::disjoint` as a convenience function.
-  pub fn merge(mut self, other: Span<'n, 't>)
-    -> Result<Span<'n, 't>, IncompatibleSourcesError<'n, 't, 'n, 't>>
+  pub fn merge(self, other: Span<'n, 't, T>)
+    -> Result<Span<'n, 't, T>, IncompatibleSourcesError<'n, 't, T>>
   {
-    if  self.source != other.source {
+    if !self.same_source(&other) {
       return Err(
         IncompatibleSourcesError{
           lhs: self,
@@ -107,19 +138,18 @@ impl<'n, 't> Span<'n, 't> {
     let end    = max(self.end(), other.end());
     let length = end - start;
 
-    let mut new_span = Self {
-      start,
-      length,
-      source: self.source
-    };
-
-    std::mem::swap( & mut self, & mut new_span);
-    Ok(self)
+    Ok(
+      Self {
+        start,
+        length,
+        source: self.source
+      }
+    )
   }
 
   /// A helper function to tell whether two spans do not overlap.
-  pub fn disjoint(self, other: Span) -> bool {
-    if self.source != other.source {
+  pub fn disjoint(self, other: Span<'n, 't, T>) -> bool {
+    if !self.same_source(&other) {
       return true;
     }
 
@@ -132,6 +162,92 @@ impl<'n, 't> Span<'n, 't> {
     first.end() <= last.start
   }
 
+  /// The span covered by both `self` and `other`, or `None` if they neither overlap nor touch.
+  /// Spans that touch intersect in an empty span.
+  pub fn intersect(self, other: Span<'n, 't, T>)
+    -> Result<Option<Span<'n, 't, T>>, IncompatibleSourcesError<'n, 't, T>>
+  {
+    self.check_same_source(other)?;
+
+    let start = max(self.start, other.start);
+    let end   = min(self.end(), other.end());
+
+    Ok((start <= end).then(|| self.with_bounds(start, end)))
+  }
+
+  /// The parts of `self` before and after `other`. Either part is `None` if it would be empty, so
+  /// both are `None` if `other` contains `self`, and `self` is the only part if they are disjoint.
+  pub fn subtract(self, other: Span<'n, 't, T>)
+    -> Result<Difference<'n, 't, T>, IncompatibleSourcesError<'n, 't, T>>
+  {
+    self.check_same_source(other)?;
+
+    let before_end  = min(self.end(), other.start);
+    let after_start = max(self.start, other.end());
+
+    let before = (self.start < before_end).then(|| self.with_bounds(self.start, before_end));
+    let after  = (after_start < self.end()).then(|| self.with_bounds(after_start, self.end()));
+
+    Ok((before, after))
+  }
+
+  /// Whether `other` lies entirely within `self`.
+  pub fn contains(self, other: Span<'n, 't, T>) -> Result<bool, IncompatibleSourcesError<'n, 't, T>> {
+    self.check_same_source(other)?;
+    Ok(self.start <= other.start && other.end() <= self.end())
+  }
+
+  /// Whether the byte at `index` lies within `self`.
+  pub fn contains_index(self, index: ByteIndex) -> bool {
+    self.start <= index && index < self.end()
+  }
+
+  /// Whether one of the spans ends exactly where the other starts.
+  pub fn is_adjacent(self, other: Span<'n, 't, T>) -> Result<bool, IncompatibleSourcesError<'n, 't, T>> {
+    self.check_same_source(other)?;
+    Ok(self.end() == other.start || other.end() == self.start)
+  }
+
+  /// The span between the end of the earlier span and the start of the later one, or `None` if the
+  /// spans overlap. The gap between adjacent spans is empty.
+  pub fn gap_between(self, other: Span<'n, 't, T>)
+    -> Result<Option<Span<'n, 't, T>>, IncompatibleSourcesError<'n, 't, T>>
+  {
+    self.check_same_source(other)?;
+
+    let start = min(self.end(), other.end());
+    let end   = max(self.start, other.start);
+
+    Ok((start <= end).then(|| self.with_bounds(start, end)))
+  }
+
+  /// The empty span at the start of `self`.
+  pub fn shrink_to_lo(self) -> Span<'n, 't, T> {
+    self.with_bounds(self.start, self.start)
+  }
+
+  /// The empty span at the end of `self`.
+  pub fn shrink_to_hi(self) -> Span<'n, 't, T> {
+    self.with_bounds(self.end(), self.end())
+  }
+
+  fn check_same_source(self, other: Span<'n, 't, T>) -> Result<(), IncompatibleSourcesError<'n, 't, T>> {
+    match self.same_source(&other) {
+      true => Ok(()),
+      false => Err(IncompatibleSourcesError{ lhs: self, rhs: other }),
+    }
+  }
+
+  /// The span of `self.source` from `start` to `end`.
+  fn with_bounds(self, start: ByteIndex, end: ByteIndex) -> Span<'n, 't, T> {
+    Span::new(start, end - start, self.source)
+  }
+
+  /// Whether `self` and `other` refer to the same `Source` object.
+  pub fn same_source(&self, other: &Span<'n, '_, T>) -> bool {
+    std::ptr::eq(self.source, other.source)
+  }
+
 
   pub fn start(self) -> ByteIndex {
     self.start
@@ -139,17 +255,18 @@ impl<'n, 't> Span<'n, 't> {
 
 
   pub fn end(self) -> ByteIndex {
-    self.start + self.len().into()
+    self.start + self.length
   }
 
 
-  // Create a new span from a start and fragment.
+  // Create a new span from a start and fragment. Panics if `start` is a `usize` larger than
+  // `RawIndex::MAX`. (See `Span::try_new`.)
   pub fn new<S: Into<ByteIndex>, L: Into<ByteOffset>>(
     start : S,
     length: L,
-    source: &'t Source<'n, 't>
+    source: &'t Source<'n, T>
     // todo: Consider adding `extra` as in `Span`.
-  ) -> Span<'n, 't>
+  ) -> Span<'n, 't, T>
   {
     let start  = start.into();
     let length = length.into();
@@ -161,40 +278,281 @@ impl<'n, 't> Span<'n, 't> {
     }
   }
 
+  /// Like `Span::new`, but gives an error instead of panicking if the start or end of the span is
+  /// larger than `RawIndex::MAX`.
+  pub fn try_new(start: usize, length: usize, source: &'t Source<'n, T>)
+    -> Result<Span<'n, 't, T>, IndexOverflowError>
+  {
+    try_raw_index(start.saturating_add(length))?;
+
+    Ok(Span::new(ByteIndex::try_new(start)?, length, source))
+  }
+
+
+  /// The `SourceId` of this span's source, if the source belongs to a `Sources` database.
+  pub fn source_id(&self) -> Option<SourceId> {
+    self.source.id()
+  }
+
+  /// Gives the `IdSpan` equivalent to this span, which refers to its source by `SourceId` instead
+  /// of by reference. Returns `None` if the source does not belong to a `Sources` database.
+  pub fn id_span(&self) -> Option<IdSpan> {
+    Some(IdSpan::new(self.start, self.length, self.source_id()?))
+  }
+
+
+  /// Gives the `CompactSpan` encoding of this span, interning it in `sources` if necessary.
+  /// Returns `None` if the source does not belong to a `Sources` database.
+  pub fn compact(&self, sources: &Sources<'n, T>) -> Option<CompactSpan> {
+    Some(sources.compact(self.id_span()?))
+  }
+
+
+  /// The macro expansion that produced this span's source, or `None` if the source is not
+  /// synthetic.
+  pub fn expansion(&self) -> Option<Expansion> {
+    self.source.expansion()
+  }
+
+  /// Walks outward through the stack of macro expansions that produced this span, innermost
+  /// first. `sources` must be the database this span's source belongs to.
+  pub fn expansions<'s>(&self, sources: &'s Sources<'n, T>) -> Expansions<'s, 'n, T> {
+    Expansions::new(sources, self.expansion())
+  }
+
+  /// The call site of the outermost macro invocation this span was produced by, i.e. the span
+  /// of source text the user actually wrote. If this span is not the product of macro expansion,
+  /// this is the span itself. Returns `None` only if this span's source is not in a database.
+  pub fn source_callsite(&self, sources: &Sources<'n, T>) -> Option<IdSpan> {
+    match self.expansions(sources).last() {
+      Some(expansion) => Some(expansion.call_site),
+      None => self.id_span(),
+    }
+  }
+
 
-  pub fn fragment(&self) -> &'t str {
+  /// The slice of the source text this span covers, either a `&str` or a `&[u8]` depending on
+  /// the source's text type.
+  pub fn fragment(&self) -> &'t T::Slice {
     self.source.fragment(self)
   }
 
   /// The line number of the start of the fragment in the source file. Lines
   /// start at line 1. You probably want to use `self.location(..)` instead.
-  pub fn location_line(&self) -> Result<LineNumber, LocationError> {
+  pub fn location_line(&self) -> Result<LineNumber, LocationError<'n, 't, T>> {
     Ok((self.source.line_index(self.start)?).number())
   }
 
   /// The line number of the start of the fragment in the source file. Lines
   /// start at line 1. You probably want to use `self.location(..)` instead.
-  pub fn row(&self) -> Result<LineNumber, LocationError> {
+  pub fn row(&self) -> Result<LineNumber, LocationError<'n, 't, T>> {
     self.location_line()
   }
 
   /// Gives the column number (counting UTF-8 characters) of the start of the fragment. Columns
   /// start at 1. You almost certainly want to use `self.location(..)` instead of this function.
-  pub fn column(&self) -> Result<ColumnNumber, LocationError> {
+  pub fn column(&self) -> Result<ColumnNumber, LocationError<'n, 't, T>> {
     let location = self.location()?;
     Ok(location.column_index.number())
   }
 
   /// Provides the (row_index, column_index) location of the start of the span. The row/column
-  /// indices start at 0.
-  pub fn location(&self) -> Result<Location, LocationError> {
-    self.source.location_utf8(self.start)
+  /// indices start at 0. The column is counted in the source's position encoding. (See
+  /// `Source::set_position_encoding`.)
+  pub fn location(&self) -> Result<Location, LocationError<'n, 't, T>> {
+    self.source.location(self.start)
+  }
+
+  /// Provides the location of the start of the span with the column counted in `encoding`
+  /// rather than in the source's position encoding.
+  pub fn location_in(&self, encoding: PositionEncoding)
+    -> Result<Location, LocationError<'n, 't, T>>
+  {
+    self.source.location_in(self.start, encoding)
+  }
+
+  /// Provides the location of the end of the span, the position just past its last byte, with
+  /// the column counted in the source's position encoding.
+  pub fn end_location(&self) -> Result<Location, LocationError<'n, 't, T>> {
+    self.source.location(self.end())
+  }
+
+  /// The indices of the first and last lines the span touches. A non-empty span ending just
+  /// after a line terminator does not touch the line following the terminator.
+  pub fn line_range(&self) -> Result<RangeInclusive<LineIndex>, LocationError<'n, 't, T>> {
+    let first = self.source.line_index(self.start)?;
+    let last  =
+        if self.is_empty() {
+          first
+        } else {
+          self.source.line_index(self.end() - ByteOffset::new(1))?
+        };
+
+    Ok(first..=last)
+  }
+
+  /// Splits the span into one piece per line it touches. Each piece excludes the line's
+  /// terminator and comes with its start and end columns in the source's position encoding, as
+  /// needed to underline the span line by line.
+  pub fn line_pieces(&self) -> Result<LinePieces<'n, 't, T>, LocationError<'n, 't, T>> {
+    self.line_pieces_in(self.source.position_encoding())
+  }
+
+  /// Like `self.line_pieces()`, but with the columns counted in `encoding` rather than in the
+  /// source's position encoding.
+  pub fn line_pieces_in(&self, encoding: PositionEncoding)
+    -> Result<LinePieces<'n, 't, T>, LocationError<'n, 't, T>>
+  {
+    let lines = self.line_range()?;
+
+    Ok(
+      LinePieces {
+        lines   : Lines::new(*self, lines),
+        encoding,
+      }
+    )
+  }
+
+  /// Iterates over the lines the span touches, giving the index of each line and the part of the
+  /// span on it without the line's terminator. An empty span gives one empty line.
+  pub fn lines(&self) -> Lines<'n, 't, T> {
+    match self.line_range() {
+      Ok(lines) => Lines::new(*self, lines),
+      // Only a span past the end of its source has no lines.
+      Err(_) => Lines::new(*self, LineIndex(1)..=LineIndex(0)),
+    }
+  }
+
+  /// Iterates over the characters of the span, giving the `ByteIndex` of each in the source.
+  /// Bytes of a byte source that are not valid UTF-8 are given as U+FFFD REPLACEMENT CHARACTER.
+  pub fn char_indices(&self) -> impl 't + Iterator<Item=(ByteIndex, char)> {
+    let start = self.start;
+    let bytes = self.fragment().as_bytes();
+
+    PositionEncoding::Utf32
+      .units(bytes)
+      .map(move |unit| {
+        let c = std::str::from_utf8(&bytes[unit.bytes.clone()])
+            .ok()
+            .and_then(|c| c.chars().next())
+            .unwrap_or(char::REPLACEMENT_CHARACTER);
+        (start + ByteOffset::new(unit.bytes.start), c)
+      })
+  }
+
+  /// Iterates over the characters of the span as one-character spans. Each byte of a byte source
+  /// that is not valid UTF-8 gets a span of its own.
+  pub fn char_spans(&self) -> impl 't + Iterator<Item=Span<'n, 't, T>> {
+    let span = *self;
+
+    PositionEncoding::Utf32
+      .units(self.fragment().as_bytes())
+      .map(move |unit| span.slice(unit.bytes))
   }
 
 }
 
 
-impl<'n, 't> Display for Span<'n, 't> {
+/// The parts of a span before and after another span. (See `Span::subtract`.)
+pub type Difference<'n, 't, T = &'t str> = (Option<Span<'n, 't, T>>, Option<Span<'n, 't, T>>);
+
+
+/// The part of a `Span` on a single line. (See `Span::line_pieces`.)
+pub struct LinePiece<'n, 't, T: SourceText = &'t str> {
+  pub line        : LineIndex,
+  pub span        : Span<'n, 't, T>,
+  pub start_column: ColumnIndex,
+  pub end_column  : ColumnIndex,
+}
+
+impl<'n, 't, T: SourceText> Clone for LinePiece<'n, 't, T> {
+  fn clone(&self) -> Self {
+    *self
+  }
+}
+
+impl<'n, 't, T: SourceText> Copy for LinePiece<'n, 't, T> {}
+
+impl<'n, 't, T: SourceText> Debug for LinePiece<'n, 't, T> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("LinePiece")
+     .field("line", &self.line)
+     .field("span", &self.span)
+     .field("start_column", &self.start_column)
+     .field("end_column", &self.end_column)
+     .finish()
+  }
+}
+
+
+/// An iterator over the lines of a span, created by `Span::lines` and `Source::lines`.
+pub struct Lines<'n, 't, T: SourceText = &'t str> {
+  span     : Span<'n, 't, T>,
+  next_line: LineIndex,
+  last_line: LineIndex,
+}
+
+impl<'n, 't, T: SourceText> Lines<'n, 't, T> {
+  fn new(span: Span<'n, 't, T>, lines: RangeInclusive<LineIndex>) -> Self {
+    Lines {
+      span,
+      next_line: *lines.start(),
+      last_line: *lines.end(),
+    }
+  }
+}
+
+impl<'n, 't, T: SourceText> Iterator for Lines<'n, 't, T> {
+  type Item = (LineIndex, Span<'n, 't, T>);
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.next_line > self.last_line {
+      return None;
+    }
+    let line = self.next_line;
+    self.next_line = line + LineOffset::new(1);
+
+    let content = self.span.source.line_span_without_terminator(line).ok()?;
+    // A span may start inside of the terminator of its first line.
+    let start   = self.span.start.clamp(content.start, content.end());
+    let end     = self.span.end().clamp(start, content.end());
+
+    Some((line, Span::new(start, end - start, self.span.source)))
+  }
+}
+
+
+/// An iterator over the `LinePiece`s of a span, created by `Span::line_pieces`.
+pub struct LinePieces<'n, 't, T: SourceText = &'t str> {
+  lines   : Lines<'n, 't, T>,
+  encoding: PositionEncoding,
+}
+
+impl<'n, 't, T: SourceText> Iterator for LinePieces<'n, 't, T> {
+  type Item = LinePiece<'n, 't, T>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let (line, span) = self.lines.next()?;
+    let source       = span.source;
+    let line_start   = source.line_start(line).ok()?;
+
+    let line_text    = &source.text().as_bytes()[usize::from(line_start)..usize::from(span.end())];
+    let start_column = self.encoding.columns(&line_text[..usize::from(span.start - line_start)]);
+    let end_column   = self.encoding.columns(line_text);
+
+    Some(
+      LinePiece {
+        line,
+        span,
+        start_column: start_column.into(),
+        end_column  : end_column.into(),
+      }
+    )
+  }
+}
+
+
+impl<'n, 't, T: SourceText> Display for Span<'n, 't, T> {
   fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
     let location = match self.location() {
       Ok(loc) => loc,
@@ -207,94 +565,105 @@ impl<'n, 't> Display for Span<'n, 't> {
       }
     };
 
-    if self.len() > 9 {
-      let end = self.len() - 4;
-
-      write!(
-        f,
-        "Span<{}:{}:{}>(`{}…{}`)",
-        self.source.name(),
-        location.line_index.number(),
-        location.column_index.number(),
-        self.fragment().slice(0..4),
-        self.fragment().slice(end..self.len())
-      )
-    } else {
-      write!(
-        f,
-        "Span<{}:{}:{}>(`{}`)",
-        self.source.name(),
-        location.line_index.number(),
-        location.column_index.number(),
-        self.fragment()
-      )
-    }
+    write!(
+      f,
+      "Span<{}:{}:{}>(`{}`)",
+      self.source.name(),
+      location.line_index.number(),
+      location.column_index.number(),
+      clip(&self.fragment().to_str_lossy(), 9)
+    )
   }
 }
 
 // The following are needed for `nom` integration but are also useful in themselves.
 
-impl<'n, 't> From<Span<'n, 't>> for Range<usize> {
-  fn from(span: Span<'n, 't>) -> Range<usize> {
+impl<'n, 't, T: SourceText> From<Span<'n, 't, T>> for Range<usize> {
+  fn from(span: Span<'n, 't, T>) -> Range<usize> {
     span.start.into()..span.end().into()
   }
 }
 
-impl<'n, 't> From<Span<'n, 't>> for Range<RawIndex> {
-  fn from(span: Span<'n, 't>) -> Range<RawIndex> {
+impl<'n, 't, T: SourceText> From<Span<'n, 't, T>> for Range<RawIndex> {
+  fn from(span: Span<'n, 't, T>) -> Range<RawIndex> {
     span.start.0..span.end().0
   }
 }
 
 
-impl<'n, 't, RangeType> Slice<RangeType> for Span<'n, 't>
-  where RangeType: RangeBounds<usize>
+/// Slicing is relative to the start of the span, and the result is clipped to the span, so the
+/// result is always a (possibly empty) subspan of `self`.
+impl<'n, 't, T, RangeType> Slice<RangeType> for Span<'n, 't, T>
+  where RangeType: RangeBounds<usize>,
+        T: SourceText
 {
   fn slice(&self, range: RangeType) -> Self {
+    let range_end =
+        match range.end_bound() {
+          Bound::Included(s) => { s + 1 }
+          Bound::Excluded(s) => { *s }
+          Bound::Unbounded => { self.len() }
+        };
+    let range_end = min(range_end, self.len());
     let range_start =
         match range.start_bound() {
           Bound::Included(s) => { *s }
           Bound::Excluded(s) => { s + 1 }
           Bound::Unbounded => { 0 }
         };
-    let range_end =
-        match range.end_bound() {
-          Bound::Included(s) => { *s }
-          Bound::Excluded(s) => { s - 1 }
-          Bound::Unbounded => { self.len() }
-        };
+    let range_start = min(range_start, range_end);
 
-    let start: ByteIndex = max(self.start().into(), range_start).into();
-    let length: ByteOffset = max(0, min(self.len().into(), range_end - range_start)).into();
-
-    Span::new(start, length, self.source)
+    Span::new(
+      self.start + ByteOffset::new(range_start),
+      range_end - range_start,
+      self.source
+    )
   }
 }
 
 
-impl<'n, 't> PartialEq for Span<'n, 't>
-
-{
+impl<'n, 't, T: SourceText> PartialEq for Span<'n, 't, T> {
   fn eq(&self, other: &Self) -> bool {
-    *self.source == *other.source &&
+    std::ptr::eq(self.source, other.source) &&
         self.start == other.start &&
         self.length == other.length
   }
 }
 
-impl<'n, 't> Eq for Span<'n, 't>{}
+impl<'n, 't, T: SourceText> Eq for Span<'n, 't, T> {}
 
+/// Spans of the same source are ordered by start, then by end, so of two spans starting at the same
+/// index the shorter comes first. Spans of different sources are incomparable.
+impl<'n, 't, T: SourceText> PartialOrd for Span<'n, 't, T> {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    match std::ptr::eq(self.source, other.source) {
+      true => Some((self.start, self.end()).cmp(&(other.start, other.end()))),
+      false => None,
+    }
+  }
+}
 
-// endregion
+// Consistent with `PartialEq`, which compares sources by identity.
+impl<'n, 't, T: SourceText> Hash for Span<'n, 't, T> {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.start.hash(state);
+    self.length.hash(state);
+    std::ptr::hash(self.source, state);
+  }
+}
 
 
-#[cfg(feature = "nom-parsing")]
-pub use nom_impls::*;
-use crate::error::IncompatibleSourcesError; // A module defined below.
+// endregion
+
 
 #[cfg(feature = "nom-parsing")]
 mod nom_impls {
   use super::*;
+  use std::{
+    num::NonZeroUsize,
+    ops::{RangeFrom, RangeTo},
+    str::FromStr,
+  };
   use nom::{
     ExtendInto,
     error::{ErrorKind, ParseError},
@@ -303,11 +672,14 @@ mod nom_impls {
     CompareResult,
     Err,
     FindSubstring,
+    FindToken,
+    HexDisplay,
     IResult,
     InputIter,
     InputLength,
     InputTake,
     InputTakeAtPosition,
+    Needed,
     Offset,
     ParseTo,
     Slice,
@@ -320,19 +692,50 @@ mod nom_impls {
   // region Macros
 
 
-  impl<'n, 't> AsBytes for Span<'n, 't> {
+  impl<'n, 't, T: SourceText> AsBytes for Span<'n, 't, T> {
     fn as_bytes(&self) -> &[u8] {
       self.fragment().as_bytes()
     }
   }
 
-  impl<'n, 't> InputLength for Span<'n, 't>{
+  impl<'n, 't, T: SourceText> InputLength for Span<'n, 't, T> {
     fn input_len(&self) -> usize {
-      self.fragment().as_bytes().len()
+      self.len()
+    }
+  }
+
+  impl<'n, 't, T> InputIter for Span<'n, 't, T>
+    where T: SourceText,
+          &'t T::Slice: InputIter
+  {
+    type Item     = <&'t T::Slice as InputIter>::Item;
+    type Iter     = <&'t T::Slice as InputIter>::Iter;
+    type IterElem = <&'t T::Slice as InputIter>::IterElem;
+
+    #[inline]
+    fn iter_indices(&self) -> Self::Iter {
+      self.fragment().iter_indices()
+    }
+
+    #[inline]
+    fn iter_elements(&self) -> Self::IterElem {
+      self.fragment().iter_elements()
+    }
+
+    #[inline]
+    fn position<P>(&self, predicate: P) -> Option<usize>
+      where P: Fn(Self::Item) -> bool
+    {
+      self.fragment().position(predicate)
+    }
+
+    #[inline]
+    fn slice_index(&self, count: usize) -> Result<usize, Needed> {
+      self.fragment().slice_index(count)
     }
   }
 
-  impl<'n, 't> InputTake for Span<'n, 't>
+  impl<'n, 't, T: SourceText> InputTake for Span<'n, 't, T>
     where
         Self: Slice<RangeFrom<usize>> + Slice<RangeTo<usize>>,
   {
@@ -345,12 +748,14 @@ mod nom_impls {
     }
   }
 
-  impl<'n, 't> InputTakeAtPosition for Span<'n, 't>
+  impl<'n, 't, T> InputTakeAtPosition for Span<'n, 't, T>
     //
     //     Slice<RangeFrom<usize>> + Slice<RangeTo<usize>> + Copy + AsBytes
-    where Self: InputTake
+    where T: SourceText,
+          &'t T::Slice: InputIter,
+          Self: InputTake
   {
-    type Item = <&'t str as InputIter>::Item;
+    type Item = <&'t T::Slice as InputIter>::Item;
 
     fn split_at_position<P, E: ParseError<Self>>(&self, predicate: P) -> IResult<Self, Self, E>
       where
@@ -370,7 +775,7 @@ mod nom_impls {
       where P: Fn(Self::Item) -> bool,
     {
       match self.fragment().position(predicate) {
-        Some(0) => Err(Err::Error(E::from_error_kind(self.clone(), e))),
+        Some(0) => Err(Err::Error(E::from_error_kind(*self, e))),
         Some(n) => Ok(self.take_split(n)),
         None => Err(Err::Incomplete(nom::Needed::Size(NonZeroUsize::new(1).unwrap()))),
       }
@@ -396,11 +801,11 @@ mod nom_impls {
       where P: Fn(Self::Item) -> bool,
     {
       match self.fragment().position(predicate) {
-        Some(0) => Err(Err::Error(E::from_error_kind(self.clone(), e))),
+        Some(0) => Err(Err::Error(E::from_error_kind(*self, e))),
         Some(n) => Ok(self.take_split(n)),
         None => {
           if self.input_len() == 0 {
-            Err(Err::Error(E::from_error_kind(self.clone(), e)))
+            Err(Err::Error(E::from_error_kind(*self, e)))
           } else {
             Ok(self.take_split(self.input_len()))
           }
@@ -410,68 +815,92 @@ mod nom_impls {
   }
 
 
-  impl<'n, 't> Compare<Span<'n, 't>> for Span<'n, 't> {
+  impl<'n, 't, T> Compare<Span<'n, 't, T>> for Span<'n, 't, T>
+    where T: SourceText,
+          &'t T::Slice: Compare<&'t T::Slice>
+  {
     #[inline(always)]
-    fn compare(&self, t: Span<'n, 't>) -> CompareResult {
+    fn compare(&self, t: Span<'n, 't, T>) -> CompareResult {
       self.fragment().compare(t.fragment())
     }
 
     #[inline(always)]
-    fn compare_no_case(&self, t: Span<'n, 't>) -> CompareResult {
+    fn compare_no_case(&self, t: Span<'n, 't, T>) -> CompareResult {
       self.fragment().compare_no_case(t.fragment())
     }
   }
 
-  impl<'n, 't> Compare<&str> for Span<'n, 't> {
+  impl<'n, 't, 'b, T> Compare<&'b str> for Span<'n, 't, T>
+    where T: SourceText,
+          &'t T::Slice: Compare<&'b str>
+  {
     #[inline(always)]
-    fn compare(&self, t: &str) -> CompareResult {
+    fn compare(&self, t: &'b str) -> CompareResult {
       self.fragment().compare(t)
     }
 
     #[inline(always)]
-    fn compare_no_case(&self, t: &str) -> CompareResult {
+    fn compare_no_case(&self, t: &'b str) -> CompareResult {
       self.fragment().compare_no_case(t)
     }
   }
 
-  // TODO(future): replace impl_compare! with below default specialization?
-  // default impl<A: Compare<B>, B> Compare<B> for Span<'s, A> {
-  //     #[inline(always)]
-  //     fn compare(&self, t: B) -> CompareResult {
-  //         self.source.compare(t)
-  //     }
-  //
-  //     #[inline(always)]
-  //     fn compare_no_case(&self, t: B) -> CompareResult {
-  //         self.source.compare_no_case(t)
-  //     }
-  // }
+  impl<'n, 't, 'b, T> Compare<&'b [u8]> for Span<'n, 't, T>
+    where T: SourceText,
+          &'t T::Slice: Compare<&'b [u8]>
+  {
+    #[inline(always)]
+    fn compare(&self, t: &'b [u8]) -> CompareResult {
+      self.fragment().compare(t)
+    }
 
+    #[inline(always)]
+    fn compare_no_case(&self, t: &'b [u8]) -> CompareResult {
+      self.fragment().compare_no_case(t)
+    }
+  }
 
-  // todo: Do we need FindToken?
 
-  // impl<'s, Token> FindToken<Token> for Span<'n, 't>
-  // {
-  //   fn find_token(&self, token: Token) -> bool {
-  //     self.fragment().find_token(token)
-  //   }
-  // }
+  impl<'n, 't, T, Token> FindToken<Token> for Span<'n, 't, T>
+    where T: SourceText,
+          &'t T::Slice: FindToken<Token>
+  {
+    fn find_token(&self, token: Token) -> bool {
+      self.fragment().find_token(token)
+    }
+  }
+
+  impl<'n, 't, 'b, T> FindSubstring<&'b str> for Span<'n, 't, T>
+    where T: SourceText,
+          &'t T::Slice: FindSubstring<&'b str>
+  {
+    #[inline]
+    fn find_substring(&self, substr: &'b str) -> Option<usize> {
+      self.fragment().find_substring(substr)
+    }
+  }
 
-  impl<'n, 't> FindSubstring<&'t str> for Span<'n, 't> {
+  impl<'n, 't, 'b, T> FindSubstring<&'b [u8]> for Span<'n, 't, T>
+    where T: SourceText,
+          &'t T::Slice: FindSubstring<&'b [u8]>
+  {
     #[inline]
-    fn find_substring(&self, substr: &str) -> Option<usize> {
+    fn find_substring(&self, substr: &'b [u8]) -> Option<usize> {
       self.fragment().find_substring(substr)
     }
   }
 
-  impl<'n, 't, R: FromStr> ParseTo<R> for Span<'n, 't> {
+  impl<'n, 't, T, R: FromStr> ParseTo<R> for Span<'n, 't, T>
+    where T: SourceText,
+          &'t T::Slice: ParseTo<R>
+  {
     #[inline]
     fn parse_to(&self) -> Option<R> {
       self.fragment().parse_to()
     }
   }
 
-  impl<'n, 't> Offset for Span<'n, 't> {
+  impl<'n, 't, T: SourceText> Offset for Span<'n, 't, T> {
     fn offset(&self, second: &Self) -> usize {
       let fst = self.start;
       let snd = second.start;
@@ -481,23 +910,29 @@ mod nom_impls {
   }
 
 
-  impl<'n, 't> ExtendInto for Span<'n, 't> {
-    type Item = char;
-    type Extender = String;
+  impl<'n, 't, T> ExtendInto for Span<'n, 't, T>
+    where T: SourceText,
+          &'t T::Slice: ExtendInto
+  {
+    type Item     = <&'t T::Slice as ExtendInto>::Item;
+    type Extender = <&'t T::Slice as ExtendInto>::Extender;
 
     #[inline]
     fn new_builder(&self) -> Self::Extender {
-      self.fragment().new_builder()
+      <&'t T::Slice as ExtendInto>::new_builder(&self.fragment())
     }
 
     #[inline]
     fn extend_into(&self, acc: &mut Self::Extender) {
-      self.fragment().extend_into(acc)
+      <&'t T::Slice as ExtendInto>::extend_into(&self.fragment(), acc)
     }
   }
 
 
-  impl<'n, 't> nom::HexDisplay for Span<'n, 't> {
+  impl<'n, 't, T> HexDisplay for Span<'n, 't, T>
+    where T: SourceText,
+          T::Slice: HexDisplay
+  {
     fn to_hex(&self, chunk_size: usize) -> String {
       self.fragment().to_hex(chunk_size)
     }
@@ -517,7 +952,7 @@ mod nom_impls {
 
   /// Capture the position of the current fragment
   #[allow(unused)]
-  pub fn position<'s, E>(text: &'s str) -> IResult<&str, &str, E>
+  pub fn position<'s, E>(text: &'s str) -> IResult<&'s str, &'s str, E>
     where
         E: ParseError<&'s str>
   {
@@ -527,4 +962,3 @@ mod nom_impls {
 
 
 }
-
@@ -0,0 +1,329 @@
+/*!
+
+A `SpanTree` maps `IdSpan`s to values and answers the questions an editor asks of an AST: which
+nodes contain the cursor, which nodes overlap a selection, and which is the innermost node at the
+cursor. Each source has its own balanced interval tree, ordered by the start of the spans and
+augmented with the largest end in each subtree, so queries only visit the parts of the tree that
+can match. A `Span` of a source in a `Sources` database can be converted with `Span::id_span`.
+
+```
+use saucepan::{ByteIndex, IdSpan, SourceId, SpanTree};
+
+let id = SourceId(0);
+let mut tree = SpanTree::new();
+// `f(x + 1)`
+tree.insert(IdSpan::new(0usize, 8usize, id), "call");
+tree.insert(IdSpan::new(2usize, 5usize, id), "sum");
+tree.insert(IdSpan::new(2usize, 1usize, id), "x");
+tree.insert(IdSpan::new(6usize, 1usize, id), "1");
+
+let at_x: Vec<_> = tree.at(id, ByteIndex(2)).map(|(_, node)| *node).collect();
+assert_eq!(at_x, ["call", "sum", "x"]);
+assert_eq!(tree.innermost_at(id, ByteIndex(4)).map(|(_, node)| *node), Some("sum"));
+```
+
+*/
+
+use std::collections::HashMap;
+
+use crate::{
+  ByteIndex,
+  ByteOffset,
+  IdSpan,
+  RawIndex,
+  SourceId,
+};
+
+
+/// A collection of values keyed by `IdSpan`, which may overlap or nest, supporting queries by
+/// position. Spans of different sources never match each other's queries.
+#[derive(Clone, Debug)]
+pub struct SpanTree<T> {
+  trees: HashMap<SourceId, Tree<T>>,
+  len  : usize,
+}
+
+impl<T> Default for SpanTree<T> {
+  fn default() -> Self {
+    SpanTree {
+      trees: HashMap::new(),
+      len  : 0,
+    }
+  }
+}
+
+impl<T> SpanTree<T> {
+  pub fn new() -> SpanTree<T> {
+    SpanTree::default()
+  }
+
+  /// The number of entries in the tree across all sources.
+  pub fn len(&self) -> usize {
+    self.len
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  pub fn clear(&mut self) {
+    self.trees.clear();
+    self.len = 0;
+  }
+
+  /// Adds `value` under `span`. Any number of values may be added under the same span.
+  pub fn insert(&mut self, span: IdSpan, value: T) {
+    self.trees.entry(span.source_id()).or_default().insert(span, value);
+    self.len += 1;
+  }
+
+  /// The entries whose spans contain the byte at `index` of the source with id `source_id`, in
+  /// order of their start and, for equal starts, longest first. When the spans are properly
+  /// nested, this is outermost first.
+  pub fn at(&self, source_id: SourceId, index: ByteIndex) -> SpanTreeQuery<'_, T> {
+    match index.0.checked_add(1) {
+      Some(end) => self.query(source_id, index, ByteIndex(end)),
+      // No span ends past `RawIndex::MAX`, so none contains the byte there.
+      None => SpanTreeQuery { nodes: &[], stack: Vec::new(), last_start: index, first_end: index },
+    }
+  }
+
+  /// The entries whose spans share at least one byte with `span`, in the order of `SpanTree::at`.
+  /// An empty `span` overlaps the spans containing the byte at its position.
+  pub fn overlapping(&self, span: IdSpan) -> SpanTreeQuery<'_, T> {
+    match span.is_empty() {
+      true  => self.at(span.source_id(), span.start()),
+      false => self.query(span.source_id(), span.end() - ByteOffset(1), span.start() + ByteOffset(1)),
+    }
+  }
+
+  /// The entries whose spans contain all of `span`, in the order of `SpanTree::at`. An empty
+  /// `span` is contained in a span that ends at its position, so this is the query to use for a
+  /// cursor placed just after the last character of a node.
+  pub fn containing(&self, span: IdSpan) -> SpanTreeQuery<'_, T> {
+    self.query(span.source_id(), span.start(), span.end())
+  }
+
+  /// The entry with the shortest span containing the byte at `index`. Of several shortest entries,
+  /// the last in the order of `SpanTree::at` is returned, which for equal spans is the one
+  /// inserted last.
+  pub fn innermost_at(&self, source_id: SourceId, index: ByteIndex) -> Option<(IdSpan, &T)> {
+    innermost(self.at(source_id, index))
+  }
+
+  /// The entry with the shortest span containing all of `span`. Ties are broken as by
+  /// `SpanTree::innermost_at`.
+  pub fn innermost_containing(&self, span: IdSpan) -> Option<(IdSpan, &T)> {
+    innermost(self.containing(span))
+  }
+
+  /// All entries of the source with id `source_id`, in the order of `SpanTree::at`.
+  pub fn iter_source(&self, source_id: SourceId) -> SpanTreeQuery<'_, T> {
+    self.query(source_id, ByteIndex(RawIndex::MAX), ByteIndex(0))
+  }
+
+  /// The entries of `source_id` with spans starting at or before `last_start` and ending at or
+  /// after `first_end`.
+  fn query(&self, source_id: SourceId, last_start: ByteIndex, first_end: ByteIndex)
+    -> SpanTreeQuery<'_, T>
+  {
+    let mut query = SpanTreeQuery {
+      nodes: self.trees.get(&source_id).map_or(&[], |tree| &tree.nodes),
+      stack: Vec::new(),
+      last_start,
+      first_end,
+    };
+    query.push_left(self.trees.get(&source_id).and_then(|tree| tree.root));
+    query
+  }
+}
+
+fn innermost<'a, T>(entries: SpanTreeQuery<'a, T>) -> Option<(IdSpan, &'a T)> {
+  entries.reduce(|best, entry| if entry.0.len() <= best.0.len() { entry } else { best })
+}
+
+impl<T> Extend<(IdSpan, T)> for SpanTree<T> {
+  fn extend<I: IntoIterator<Item=(IdSpan, T)>>(&mut self, entries: I) {
+    for (span, value) in entries {
+      self.insert(span, value);
+    }
+  }
+}
+
+impl<T> FromIterator<(IdSpan, T)> for SpanTree<T> {
+  fn from_iter<I: IntoIterator<Item=(IdSpan, T)>>(entries: I) -> Self {
+    let mut tree = SpanTree::new();
+    tree.extend(entries);
+    tree
+  }
+}
+
+
+/// An iterator over entries of a `SpanTree` matching a query, created by `SpanTree::at`,
+/// `SpanTree::overlapping`, `SpanTree::containing`, or `SpanTree::iter_source`.
+pub struct SpanTreeQuery<'a, T> {
+  nodes     : &'a [Node<T>],
+  /// The nodes whose left subtrees have been visited, innermost last.
+  stack     : Vec<usize>,
+  last_start: ByteIndex,
+  first_end : ByteIndex,
+}
+
+impl<'a, T> SpanTreeQuery<'a, T> {
+  /// Pushes `node` and its chain of left children, skipping subtrees in which no span ends late
+  /// enough to match.
+  fn push_left(&mut self, mut node: Option<usize>) {
+    while let Some(index) = node {
+      let current = &self.nodes[index];
+      if current.max_end < self.first_end {
+        break;
+      }
+      self.stack.push(index);
+      node = current.left;
+    }
+  }
+}
+
+impl<'a, T> Iterator for SpanTreeQuery<'a, T> {
+  type Item = (IdSpan, &'a T);
+
+  fn next(&mut self) -> Option<Self::Item> {
+    while let Some(index) = self.stack.pop() {
+      let node = &self.nodes[index];
+      // Nodes are visited in order of their start, so no later node can match either.
+      if node.span.start() > self.last_start {
+        self.stack.clear();
+        return None;
+      }
+      self.push_left(node.right);
+      if node.span.end() >= self.first_end {
+        return Some((node.span, &node.value));
+      }
+    }
+    None
+  }
+}
+
+
+/// The AVL tree of the spans of a single source. Nodes are ordered by start and then by
+/// decreasing end, with equal spans in insertion order.
+#[derive(Clone, Debug)]
+struct Tree<T> {
+  nodes: Vec<Node<T>>,
+  root : Option<usize>,
+}
+
+#[derive(Clone, Debug)]
+struct Node<T> {
+  span   : IdSpan,
+  value  : T,
+  /// The largest end of a span in the subtree rooted at this node.
+  max_end: ByteIndex,
+  height : u32,
+  left   : Option<usize>,
+  right  : Option<usize>,
+}
+
+impl<T> Default for Tree<T> {
+  fn default() -> Self {
+    Tree {
+      nodes: Vec::new(),
+      root : None,
+    }
+  }
+}
+
+impl<T> Tree<T> {
+  fn insert(&mut self, span: IdSpan, value: T) {
+    let index = self.nodes.len();
+    self.nodes.push(Node {
+      span,
+      value,
+      max_end: span.end(),
+      height : 1,
+      left   : None,
+      right  : None,
+    });
+    self.root = Some(self.insert_below(self.root, index));
+  }
+
+  /// Inserts the node `new` into the subtree rooted at `node`, returning the subtree's new root.
+  fn insert_below(&mut self, node: Option<usize>, new: usize) -> usize {
+    let Some(node) = node else { return new };
+
+    let (span, new_span) = (self.nodes[node].span, self.nodes[new].span);
+    if new_span.start() < span.start() || (new_span.start() == span.start() && new_span.end() > span.end()) {
+      let left = self.insert_below(self.nodes[node].left, new);
+      self.nodes[node].left = Some(left);
+    } else {
+      let right = self.insert_below(self.nodes[node].right, new);
+      self.nodes[node].right = Some(right);
+    }
+
+    self.rebalance(node)
+  }
+
+  fn height(&self, node: Option<usize>) -> u32 {
+    node.map_or(0, |node| self.nodes[node].height)
+  }
+
+  /// Recomputes the height and largest end of `node` from those of its children.
+  fn update(&mut self, node: usize) {
+    let Node { span, left, right, .. } = self.nodes[node];
+    let mut max_end = span.end();
+    for child in [left, right].into_iter().flatten() {
+      max_end = max_end.max(self.nodes[child].max_end);
+    }
+    self.nodes[node].max_end = max_end;
+    self.nodes[node].height  = 1 + self.height(left).max(self.height(right));
+  }
+
+  fn balance_factor(&self, node: usize) -> i64 {
+    self.height(self.nodes[node].left) as i64 - self.height(self.nodes[node].right) as i64
+  }
+
+  /// Restores the AVL invariant at `node`, whose subtrees are balanced and differ in height by at
+  /// most two, returning the subtree's new root.
+  fn rebalance(&mut self, node: usize) -> usize {
+    self.update(node);
+    match self.balance_factor(node) {
+
+      2 => {
+        let left = self.nodes[node].left.unwrap();
+        if self.balance_factor(left) < 0 {
+          self.nodes[node].left = Some(self.rotate_left(left));
+        }
+        self.rotate_right(node)
+      }
+
+      -2 => {
+        let right = self.nodes[node].right.unwrap();
+        if self.balance_factor(right) > 0 {
+          self.nodes[node].right = Some(self.rotate_right(right));
+        }
+        self.rotate_left(node)
+      }
+
+      _ => node,
+
+    }
+  }
+
+  fn rotate_left(&mut self, node: usize) -> usize {
+    let right = self.nodes[node].right.unwrap();
+    self.nodes[node].right = self.nodes[right].left;
+    self.nodes[right].left = Some(node);
+    self.update(node);
+    self.update(right);
+    right
+  }
+
+  fn rotate_right(&mut self, node: usize) -> usize {
+    let left = self.nodes[node].left.unwrap();
+    self.nodes[node].left  = self.nodes[left].right;
+    self.nodes[left].right = Some(node);
+    self.update(node);
+    self.update(left);
+    left
+  }
+}
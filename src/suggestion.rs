@@ -0,0 +1,190 @@
+/*!
+
+A `Suggestion` proposes replacing the text of a span, e.g. inserting a missing semicolon or
+renaming a misspelled identifier. Suggestions are attached to `Diagnostic`s, which show them as an
+inline diff when rendered, and a batch of suggestions for a source can be applied at once with
+`Source::apply_suggestions`, the basis of a `--fix` mode. As in rustc, each suggestion carries an
+`Applicability` saying whether it is safe to apply without review.
+
+```
+use saucepan::{Applicability, Source, Suggestion};
+
+let source = Source::new("main.x", "let x = 1\nlet y = x + z\n");
+let suggestions = [
+  Suggestion::new(source.slice(9..9), ";", Applicability::MachineApplicable),
+  Suggestion::new(source.slice(23..23), ";", Applicability::MachineApplicable),
+  Suggestion::new(source.slice(22..23), "x", Applicability::MaybeIncorrect),
+];
+
+let fixed =
+    source.apply_suggestions(
+      suggestions.iter().filter(|suggestion| suggestion.applicability.is_machine_applicable())
+    ).unwrap();
+assert_eq!(fixed, "let x = 1;\nlet y = x + z;\n");
+```
+
+*/
+
+use std::{
+  fmt::{Debug, Formatter},
+  ops::Range,
+};
+
+#[cfg(feature = "serialization")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  AsBytes,
+  ByteIndex,
+  Source,
+  SourceText,
+  Span,
+  SuggestionError,
+  TextSlice,
+};
+
+
+/// How confident the tool making a `Suggestion` is that applying it gives the intended code. The
+/// names follow rustc.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub enum Applicability {
+  /// The suggestion is definitely what the user intended and can be applied automatically.
+  MachineApplicable,
+  /// The suggestion may be what the user intended, but should be reviewed before it is applied.
+  MaybeIncorrect,
+  /// The replacement contains placeholders like `(...)` the user must fill in.
+  HasPlaceholders,
+  /// The applicability of the suggestion is unknown.
+  #[default]
+  Unspecified,
+}
+
+impl Applicability {
+  /// Whether a suggestion may be applied without review.
+  pub fn is_machine_applicable(self) -> bool {
+    self == Applicability::MachineApplicable
+  }
+}
+
+
+/// A proposal to replace the text of `span` with `replacement`. An empty span inserts the
+/// replacement, and an empty replacement deletes the text of the span.
+pub struct Suggestion<'n, 't, T: SourceText = &'t str> {
+  pub span         : Span<'n, 't, T>,
+  pub replacement  : String,
+  pub applicability: Applicability,
+  /// A message describing the change, e.g. "add a semicolon". It should not contain line breaks.
+  pub message      : String,
+}
+
+impl<'n, 't, T: SourceText> Suggestion<'n, 't, T> {
+  pub fn new(span: Span<'n, 't, T>, replacement: impl Into<String>, applicability: Applicability) -> Self {
+    Suggestion {
+      span,
+      replacement: replacement.into(),
+      applicability,
+      message    : String::new(),
+    }
+  }
+
+  pub fn with_message(mut self, message: impl Into<String>) -> Self {
+    self.message = message.into();
+    self
+  }
+
+  /// Whether the suggestion only inserts text.
+  pub fn is_insertion(&self) -> bool {
+    self.span.is_empty()
+  }
+
+  /// Whether the suggestion only deletes text.
+  pub fn is_deletion(&self) -> bool {
+    self.replacement.is_empty() && !self.span.is_empty()
+  }
+}
+
+// `Clone` and `Debug` are implemented by hand so as not to require them of `T`.
+impl<'n, 't, T: SourceText> Clone for Suggestion<'n, 't, T> {
+  fn clone(&self) -> Self {
+    Suggestion {
+      span         : self.span,
+      replacement  : self.replacement.clone(),
+      applicability: self.applicability,
+      message      : self.message.clone(),
+    }
+  }
+}
+
+impl<'n, 't, T: SourceText> Debug for Suggestion<'n, 't, T> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("Suggestion")
+     .field("span", &self.span)
+     .field("replacement", &self.replacement)
+     .field("applicability", &self.applicability)
+     .field("message", &self.message)
+     .finish()
+  }
+}
+
+
+impl<'n, T: SourceText> Source<'n, T> {
+  /// Gives the text of the source with every suggestion in `suggestions` applied. Suggestions may
+  /// be given in any order, and duplicates are applied once. The suggestions must belong to this
+  /// source, and no two may replace overlapping text or insert different text at the same index,
+  /// as the result would depend on their order. Suggestions that merely touch are fine. A
+  /// suggestion whose span extends past the end of the text or, for string text, does not start
+  /// and end on `char` boundaries gives `InvalidSpan`. The text a byte source keeps must be valid
+  /// UTF-8, or `InvalidUtf8` is returned.
+  pub fn apply_suggestions<'s, 't, I>(&'t self, suggestions: I) -> Result<String, SuggestionError<'n, 't, T>>
+    where I: IntoIterator<Item=&'s Suggestion<'n, 't, T>>,
+          'n: 's,
+          't: 's,
+          T: 's
+  {
+    let mut suggestions: Vec<&Suggestion<T>> = suggestions.into_iter().collect();
+    if let Some(suggestion) = suggestions.iter().find(|suggestion| !std::ptr::eq(suggestion.span.source, self)) {
+      return Err(SuggestionError::WrongSource { given: suggestion.span });
+    }
+    let text = self.text();
+    for suggestion in &suggestions {
+      let span = suggestion.span;
+      if !text.is_boundary(span.start().into()) || !text.is_boundary(span.end().into()) {
+        return Err(SuggestionError::InvalidSpan { given: span });
+      }
+    }
+
+    // All spans belong to this source, so they are comparable.
+    suggestions.sort_by(|a, b| a.span.partial_cmp(&b.span).unwrap());
+    suggestions.dedup_by(|b, a| a.span == b.span && a.replacement == b.replacement);
+    for pair in suggestions.windows(2) {
+      let (first, second) = (pair[0].span, pair[1].span);
+      if second.start() < first.end() || (first == second && first.is_empty()) {
+        return Err(SuggestionError::Overlapping { first, second });
+      }
+    }
+
+    let bytes     = text.as_bytes();
+    let mut fixed = String::with_capacity(bytes.len());
+    let mut next  = 0;
+    for suggestion in suggestions {
+      let start = usize::from(suggestion.span.start());
+      fixed.push_str(utf8(bytes, next..start)?);
+      fixed.push_str(&suggestion.replacement);
+      next = usize::from(suggestion.span.end());
+    }
+    fixed.push_str(utf8(bytes, next..bytes.len())?);
+
+    Ok(fixed)
+  }
+}
+
+/// The bytes in `range` as a `str`, or an error giving the index of the first byte that is not
+/// valid UTF-8.
+fn utf8<'n, 't, T: SourceText>(bytes: &[u8], range: Range<usize>)
+  -> Result<&str, SuggestionError<'n, 't, T>>
+{
+  std::str::from_utf8(&bytes[range.clone()]).map_err(|error| {
+    SuggestionError::InvalidUtf8 { given: ByteIndex::new(range.start + error.valid_up_to()) }
+  })
+}
@@ -11,13 +11,18 @@ use nom::{
   // InputTake,
   // InputTakeAtPosition,
   // Offset,
-  Slice
 };
 
 
-use crate::{ByteIndex, ColumnNumber, LineNumber};
+use std::{ops::Range, rc::Rc};
+
+use crate::{ByteIndex, ColumnIndex, ColumnNumber, LineIndex, LineNumber, RawIndex};
+use crate::{ByteOrderMark, TextEncoding};
+use crate::{LineEnding, LineTerminators, Location, LocationError, PositionEncoding};
 use crate::source::Source;
-use crate::Span;
+use crate::{Expansion, IdSpan, LoadError, NotASourceError, Rebased, SourceId, Sources, SpanMap};
+use crate::{CompactSpan, SpanTree, SpanTreeQuery};
+use crate::{Slice, Span};
 
 static SOURCE_NAME: &str = "The Second Coming By William Butler Yeats";
 static SOURCE_TEXT: &str =
@@ -97,6 +102,7 @@ fn test_disjoint() {
 // region located span
 
 
+#[cfg(feature = "nom-parsing")]
 #[test]
 fn calculate_columns() {
   let source = Source::new(SOURCE_NAME, SOURCE_TEXT);
@@ -117,12 +123,1265 @@ fn calculate_columns_accurately_with_non_ascii_chars() {
   assert_eq!(location.column_index.number(), ColumnNumber(3));
 }
 
+#[test]
+fn columns_count_the_characters_before_the_index() {
+  // The column of a byte index is the number of characters between the start of its line and
+  // the index, not counting the character at the index. This used to count it as well, which
+  // made columns one too large and panicked on indices before a multi-byte character.
+  let source = Source::new("columns", "ab\nメカ\n");
+  let expected = [(0, 0, 0), (1, 0, 1), (3, 1, 0), (6, 1, 1), (9, 1, 2), (10, 2, 0)];
+
+  for (index, line, column) in expected {
+    let expected = Location::new(line as usize, column as usize);
+    assert_eq!(source.location(ByteIndex(index)).unwrap(), expected);
+    assert_eq!(source.location_utf8(ByteIndex(index)).unwrap(), expected);
+    assert_eq!(source.location_naive_utf8(ByteIndex(index)).unwrap(), expected);
+  }
+  assert!(source.location(ByteIndex(11)).is_err());
+}
+
 #[test]
 fn error_when_getting_column_if_offset_is_too_big() {
   let source = Source::new("some text", "");
   let location = source.location_in_bytes(ByteIndex(28));
 
-  assert_eq!(location.is_err(), true);
+  assert!(location.is_err());
+}
+
+#[test]
+fn byte_slice_sources() {
+  let source = Source::new("bytes", b"\x00\x01GIF89a\n\xff\xfe".as_slice());
+  let span = source.source_span();
+
+  assert_eq!(span.slice(2..8).fragment(), b"GIF89a");
+  #[cfg(feature = "nom-parsing")]
+  assert_eq!(span.find_substring("GIF"), Some(2));
+  // The line index works the same for byte slices as for string slices.
+  let location = span.slice(9..).location().unwrap();
+  assert_eq!(location.line_index.number(), LineNumber::from(2));
+  assert_eq!(location.column_index.number(), ColumnNumber::from(1));
+}
+
+#[test]
+fn slicing_is_relative_to_the_span() {
+  let source = Source::new(SOURCE_NAME, SOURCE_TEXT);
+  let line = source.slice(41..77);
+
+  assert_eq!(line.fragment(), "The falcon cannot hear the falconer;");
+  assert_eq!(line.slice(4..10).fragment(), "falcon");
+  assert_eq!(line.slice(4..=9).fragment(), "falcon");
+  // Ranges are clipped to the span.
+  assert_eq!(line.slice(30..100).fragment(), "coner;");
+  assert!(line.slice(100..).is_empty());
+}
+
+#[test]
+fn sources_own_text_loaded_at_runtime() {
+  let mut sources: Sources<'static, String> = Sources::new();
+
+  for i in 1..=3 {
+    let name = format!("file{}.txt", i);
+    let text = format!("line one\nline {}\n", i);
+    sources.add(name, text);
+  }
+
+  let third = sources.get(SourceId(2)).unwrap();
+  let line  = third.line_span(LineIndex(1)).unwrap();
+  assert_eq!(third.name(), "file3.txt");
+  assert_eq!(line.fragment(), "line 3\n");
+  assert_eq!(line.location().unwrap().line_index.number(), LineNumber::from(2));
+}
+
+#[test]
+fn id_spans_outlive_additions() {
+  let mut sources = Sources::new();
+  let poem = sources.add(SOURCE_NAME, SOURCE_TEXT);
+  let falcon: IdSpan = sources.get(poem).unwrap().slice(45..51).id_span().unwrap();
+
+  // Adding a source while holding `falcon` is fine.
+  let kana = sources.add("Japanese kana", "メカジキ");
+  let jiki = IdSpan::new(6usize, 6usize, kana);
+
+  assert_eq!(falcon.source_id(), poem);
+  assert_eq!(sources.fragment(falcon).unwrap(), "falcon");
+  assert_eq!(sources.name(kana).unwrap(), "Japanese kana");
+  assert_eq!(sources.fragment(jiki).unwrap(), "ジキ");
+  assert_eq!(sources.location(jiki).unwrap().column_number(), ColumnNumber(3));
+  assert_eq!(sources.span(falcon).unwrap().id_span(), Some(falcon));
+
+  let missing = SourceId(7);
+  assert_eq!(
+    sources.name(missing),
+    Err(NotASourceError { given: missing, max: SourceId(2) })
+  );
+
+  // Spans past the end of their source or inside a character are errors rather than panics.
+  let past_end = IdSpan::new(6usize, 7usize, kana);
+  let mid_char = IdSpan::new(1usize, 3usize, kana);
+  assert!(matches!(
+    sources.span(past_end),
+    Err(LocationError::OutOfBounds { given: ByteIndex(13), .. })
+  ));
+  assert!(matches!(
+    sources.fragment(mid_char),
+    Err(LocationError::InvalidCharBoundary { given: ByteIndex(1) })
+  ));
+  assert!(matches!(sources.location(past_end), Err(LocationError::OutOfBounds { .. })));
+  assert!(matches!(
+    sources.span(IdSpan::new(0usize, 1usize, missing)),
+    Err(LocationError::NotASource { given }) if given == missing
+  ));
+  assert!(sources.span(IdSpan::new(12usize, 0usize, kana)).unwrap().is_empty());
+  // A `Source` outside of a `Sources` database has no id.
+  assert_eq!(Source::new(SOURCE_NAME, SOURCE_TEXT).source_span().id_span(), None);
+}
+
+#[test]
+fn load_sources_from_the_filesystem() {
+  let dir = std::env::temp_dir().join(format!("saucepan-load-{}", std::process::id()));
+  std::fs::create_dir_all(dir.join("nested")).unwrap();
+  std::fs::write(dir.join("a.x"), "alpha\n").unwrap();
+  std::fs::write(dir.join("b.x"), "beta\n").unwrap();
+  std::fs::write(dir.join("nested").join("c.x"), "gamma\n").unwrap();
+  std::fs::write(dir.join("bad.x"), b"\xff\xfe").unwrap();
+
+  let mut sources: Sources<'static, String> = Sources::new();
+  let a = sources.load_file(dir.join("a.x")).unwrap();
+  let canonical = std::fs::canonicalize(dir.join("a.x")).unwrap();
+  assert_eq!(sources.name(a).unwrap(), canonical.to_string_lossy());
+
+  // Loading the same file through a different path gives the same source.
+  assert_eq!(sources.load_file(dir.join("nested").join("..").join("a.x")).unwrap(), a);
+  assert_eq!(sources.path_id(dir.join(".").join("a.x")), Some(a));
+
+  #[cfg(feature = "load-dir")]
+  {
+    let ids = sources.load_dir(dir.join("**").join("[abc].x").to_str().unwrap()).unwrap();
+    assert_eq!(ids.len(), 3);
+    assert_eq!(ids[0], a);
+    assert_eq!(sources.len(), 3);
+    assert_eq!(sources.fragment(sources.source_span(ids[2]).unwrap()).unwrap(), "gamma\n");
+    assert!(matches!(sources.load_dir("[unclosed"), Err(LoadError::Pattern(_))));
+  }
+
+  // I/O errors and invalid UTF-8 are reported with the offending path.
+  match sources.load_file(dir.join("missing.x")) {
+    Err(LoadError::Io { path, .. }) => assert_eq!(path, dir.join("missing.x")),
+    other => panic!("expected an I/O error, got {:?}", other),
+  }
+  assert!(matches!(sources.load_file(dir.join("bad.x")), Err(LoadError::Io { .. })));
+
+  // Byte sources accept any contents.
+  let mut binaries: Sources<'static, Vec<u8>> = Sources::new();
+  let bad = binaries.load_file(dir.join("bad.x")).unwrap();
+  assert_eq!(binaries.get(bad).unwrap().text(), b"\xff\xfe");
+
+  // The contents of "bad.x" are a UTF-16LE byte-order mark, which a decoding load understands.
+  let mut decoded: Sources<'static, String> = Sources::new();
+  let empty = decoded.load_file_decoded(dir.join("bad.x"), TextEncoding::Utf8).unwrap();
+  assert_eq!(decoded.get(empty).unwrap().text(), "");
+  assert_eq!(decoded.get(empty).unwrap().bom(), Some(ByteOrderMark::Utf16Le));
+
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn include_graph() {
+  let mut sources = Sources::new();
+  let main   = sources.add("main.x", "use a;\nuse b;\n");
+  let a      = sources.add("a.x", "use common;\n");
+  let b      = sources.add("b.x", "use common;\nuse b;\n");
+  let common = sources.add("common.x", "");
+
+  let directive = |sources: &Sources<&str>, id: SourceId, start: usize| {
+    sources.get(id).unwrap().slice(start..start + 6).id_span().unwrap()
+  };
+  let main_a   = directive(&sources, main, 0);
+  let main_b   = directive(&sources, main, 7);
+  let a_common = directive(&sources, a, 0);
+  let b_common = directive(&sources, b, 0);
+  let b_b      = directive(&sources, b, 12);
+
+  sources.add_include(a, main_a).unwrap();
+  sources.add_include(b, main_b).unwrap();
+  sources.add_include(common, a_common).unwrap();
+  sources.add_include(common, b_common).unwrap();
+  // A cycle
+  sources.add_include(b, b_b).unwrap();
+
+  assert_eq!(sources.included_from(main), None);
+  assert_eq!(sources.included_from(common), Some(a_common));
+  assert_eq!(sources.include_sites(common).collect::<Vec<_>>(), vec![a_common, b_common]);
+  assert_eq!(sources.included_by(main).collect::<Vec<_>>(), vec![a, b]);
+  assert_eq!(sources.include_chain(common), vec![a_common, main_a]);
+  assert_eq!(sources.include_chain(b), vec![main_b]);
+  assert_eq!(sources.dependencies(main), vec![a, common, b]);
+  assert_eq!(sources.dependencies(b), vec![common, b]);
+  assert_eq!(sources.includes()[0].parent(), main);
+
+  let bogus = IdSpan::new(0usize, 0usize, SourceId(9));
+  assert!(sources.add_include(a, bogus).is_err());
+}
+
+#[test]
+fn expansion_provenance() {
+  let mut sources = Sources::new();
+  let main = sources.add("main.x", "define ANSWER 42\nx = ANSWER;\n");
+  let definition = sources.get(main).unwrap().slice(0..16).id_span().unwrap();
+  let call_site  = sources.get(main).unwrap().slice(21..27).id_span().unwrap();
+  let expansion  = Expansion { call_site, definition };
+
+  let expanded = sources.add_expansion("<ANSWER>", "42", expansion).unwrap();
+  let number   = sources.get(expanded).unwrap().source_span();
+
+  assert_eq!(number.expansion(), Some(expansion));
+  assert_eq!(sources.expansion(main), None);
+  assert_eq!(number.expansions(&sources).collect::<Vec<_>>(), vec![expansion]);
+  assert_eq!(number.id_span().unwrap().source_callsite(&sources), call_site);
+  // Spans not produced by expansion are their own call site.
+  assert_eq!(call_site.source_callsite(&sources), call_site);
+  assert_eq!(call_site.expansions(&sources).count(), 0);
+
+  let bogus = Expansion { call_site: IdSpan::new(0usize, 1usize, SourceId(5)), definition };
+  assert!(matches!(
+    sources.add_expansion("<bogus>", "", bogus),
+    Err(crate::ExpansionError::NotASource(NotASourceError { given: SourceId(5), .. }))
+  ));
+}
+
+#[test]
+fn sources_share_text() {
+  let text: Rc<str> = Rc::from(SOURCE_TEXT);
+  let mut sources = Sources::new();
+  let id = sources.add(SOURCE_NAME, Rc::clone(&text));
+  let source = sources.get(id).unwrap();
+
+  assert_eq!(Rc::strong_count(&text), 2);
+  assert_eq!(source.slice(0..7).fragment(), "Turning");
+
+  let bytes = Source::new(String::from("owned name"), SOURCE_TEXT.as_bytes().to_vec());
+  assert_eq!(bytes.slice(0..7).fragment(), b"Turning");
+}
+
+#[test]
+fn apply_edit_patches_line_starts() {
+  let mut source = Source::new(SOURCE_NAME, String::from(SOURCE_TEXT));
+  let following = IdSpan::new(ByteIndex(80), 6usize, SourceId(0));
+
+  // Replace "The falcon cannot hear the falconer;\nThings" with two lines.
+  let edit = source.apply_edit(41..84, "The falcon\nhears\nThings").unwrap();
+  let mut expected = String::from(SOURCE_TEXT);
+  expected.replace_range(41..84, "The falcon\nhears\nThings");
+  let fresh = Source::new(SOURCE_NAME, expected.clone());
+
+  assert_eq!(source.text(), expected);
+  assert_eq!(source.last_line_index(), fresh.last_line_index());
+  for line in 0..=fresh.last_line_index().0 {
+    assert_eq!(source.line_start(LineIndex(line)), fresh.line_start(LineIndex(line)));
+  }
+
+  assert_eq!(edit.map_index(ByteIndex(10)), Some(ByteIndex(10)));
+  assert_eq!(edit.map_index(ByteIndex(50)), None);
+  assert_eq!(edit.map_index(ByteIndex(84)), Some(ByteIndex(64)));
+  assert_eq!(edit.map_span(following), None);
+  let moved = edit.map_span(IdSpan::new(ByteIndex(85), 4usize, SourceId(0))).unwrap();
+  assert_eq!(&source.text()[Range::<usize>::from(moved)], "fall");
+
+  assert!(source.apply_edit(0..1000, "").is_err());
+  let mut accented = Source::new("accented", String::from("caf\u{e9}"));
+  assert!(accented.apply_edit(4..5, "e").is_err());
+}
+
+#[test]
+fn rebase_spans_across_edits() {
+  let mut source = Source::new(SOURCE_NAME, String::from(SOURCE_TEXT));
+  let id = SourceId(0);
+  let turning  = IdSpan::new(ByteIndex(0), 7usize, id);  // "Turning"
+  let widening = IdSpan::new(ByteIndex(27), 8usize, id); // "widening"
+  let gyre     = IdSpan::new(ByteIndex(36), 4usize, id); // "gyre"
+  let falcon   = IdSpan::new(ByteIndex(45), 6usize, id); // "falcon"
+  let elsewhere = IdSpan::new(ByteIndex(27), 8usize, SourceId(3));
+
+  let mut map = SpanMap::new(id);
+  // "widening gyre" -> "narrowing spiral", then "Turning and turning" -> "Turning".
+  map.push(source.apply_edit(27..40, "narrowing spiral").unwrap());
+  map.push(source.apply_edit(7..19, "").unwrap());
+  assert_eq!(map.len(), 2);
+
+  let text = source.text();
+  let fragment = |span: IdSpan| &text[Range::<usize>::from(span)];
+
+  assert_eq!(map.rebase(turning), Rebased::Moved(turning));
+  assert_eq!(map.rebase(widening), Rebased::Deleted);
+  assert_eq!(map.rebase(elsewhere), Rebased::Moved(elsewhere));
+  assert_eq!(map.map_span(falcon).map(fragment), Some("falcon"));
+  assert_eq!(map.map_index(ByteIndex(50)), Some(ByteIndex(41)));
+  assert_eq!(map.map_index(ByteIndex(30)), None);
+
+  // "and turning in the" loses "and turning" but keeps " in the".
+  let partial = map.rebase(IdSpan::new(ByteIndex(8), 18usize, id));
+  assert!(matches!(partial, Rebased::PartiallyOverwritten(_)));
+  assert_eq!(partial.span().map(fragment), Some(" in the"));
+
+  // A span covering the whole edit keeps the replacement.
+  let line = map.rebase(IdSpan::new(ByteIndex(23), 17usize, id));
+  assert_eq!(line.span().map(fragment), Some("the narrowing spiral"));
+  assert_eq!(map.rebase(gyre), Rebased::Deleted);
+}
+
+#[test]
+fn insertions_move_indices_and_spans_alike() {
+  let mut source = Source::new("insert", String::from("let x = 1;"));
+  let id     = SourceId(0);
+  let x      = IdSpan::new(4usize, 1usize, id); // "x"
+  let value  = IdSpan::new(8usize, 1usize, id); // "1"
+  let before = IdSpan::new(0usize, 4usize, id); // "let "
+  let empty  = IdSpan::new(4usize, 0usize, id);
+  let whole  = IdSpan::new(0usize, 10usize, id);
+
+  // Insert "mut " at the start of "x".
+  let edit = source.apply_edit(4..4, "mut ").unwrap();
+  assert_eq!(source.text(), "let mut x = 1;");
+
+  // An index at the insertion point and a span starting there move behind the inserted text.
+  assert_eq!(edit.map_index(x.start()), Some(ByteIndex(8)));
+  assert_eq!(edit.rebase(x), Rebased::Moved(IdSpan::new(8usize, 1usize, id)));
+  assert_eq!(edit.rebase(empty), Rebased::Moved(IdSpan::new(8usize, 0usize, id)));
+  assert_eq!(edit.rebase(value).span().unwrap().start(), edit.map_index(value.start()).unwrap());
+  // A span ending at the insertion point does not grow.
+  assert_eq!(edit.rebase(before), Rebased::Moved(before));
+
+  // A span around the insertion point grows to cover the inserted text, which overwrote nothing.
+  let grown = edit.rebase(whole);
+  assert_eq!(grown, Rebased::Moved(IdSpan::new(0usize, 14usize, id)));
+  assert_eq!(&source.text()[Range::<usize>::from(grown.span().unwrap())], "let mut x = 1;");
+
+  let mut map = SpanMap::new(id);
+  map.push(edit);
+  assert_eq!(map.rebase(x).span().unwrap().start(), map.map_index(x.start()).unwrap());
+
+  // At the start of a replaced range, an index stays in front of the replacement.
+  let replace = source.apply_edit(8..9, "y").unwrap();
+  assert_eq!(replace.map_index(ByteIndex(8)), Some(ByteIndex(8)));
+}
+
+#[test]
+fn utf16_and_utf32_columns() {
+  let mut source = Source::new("emoji", "a\u{1F600}b\ncaf\u{e9}\n");
+  let b = ByteIndex(5);
+
+  assert_eq!(source.location_in(b, PositionEncoding::Utf8).unwrap(), Location::new(0usize, 5usize));
+  assert_eq!(source.location_utf16(b).unwrap(), Location::new(0usize, 3usize));
+  assert_eq!(source.location(b).unwrap(), Location::new(0usize, 2usize));
+  assert_eq!(source.slice(5..6).location().unwrap().column_index, ColumnIndex(2));
+
+  source.set_position_encoding(PositionEncoding::Utf16);
+  assert_eq!(source.slice(5..6).location().unwrap().column_index, ColumnIndex(3));
+  assert_eq!(source.slice(5..6).location_in(PositionEncoding::Utf32).unwrap().column_index, ColumnIndex(2));
+
+  assert_eq!(source.byte_index_from_utf16(LineIndex(0), ColumnIndex(3)), Ok(b));
+  assert_eq!(source.byte_index_from_utf16(LineIndex(0), ColumnIndex(4)), Ok(ByteIndex(6)));
+  assert_eq!(source.byte_index_from_utf16(LineIndex(1), ColumnIndex(4)), Ok(ByteIndex(12)));
+  assert!(matches!(
+    source.byte_index_from_utf16(LineIndex(0), ColumnIndex(2)),
+    Err(LocationError::InvalidCharBoundary { given: ByteIndex(1) })
+  ));
+  assert!(matches!(
+    source.byte_index_from_utf16(LineIndex(0), ColumnIndex(5)),
+    Err(LocationError::ColumnOutOfBounds { max: ColumnIndex(4), .. })
+  ));
+  assert!(matches!(
+    source.byte_index_from_utf16(LineIndex(3), ColumnIndex(0)),
+    Err(LocationError::LineOutOfBounds { .. })
+  ));
+}
+
+#[test]
+fn location_to_byte_index() {
+  let source = Source::new(SOURCE_NAME, SOURCE_TEXT);
+  let accented = Source::new("accented", "na\u{ef}ve\ncaf\u{e9}");
+
+  // Round trip every character boundary through each encoding.
+  for encoding in [PositionEncoding::Utf8, PositionEncoding::Utf16, PositionEncoding::Utf32] {
+    for (idx, _) in "na\u{ef}ve\ncaf".char_indices() {
+      let location = accented.location_in(ByteIndex::new(idx), encoding).unwrap();
+      assert_eq!(accented.byte_index(location, encoding), Ok(ByteIndex::new(idx)));
+    }
+  }
+
+  // Each invalid byte of a byte source is a column of its own, whichever way the column is found.
+  let invalid = Source::new("invalid", &b"\x80\x80a\n\xF0ab\n"[..]);
+  for encoding in [PositionEncoding::Utf8, PositionEncoding::Utf16, PositionEncoding::Utf32] {
+    for idx in (0..8).map(ByteIndex) {
+      let location = invalid.location_in(idx, encoding).unwrap();
+      assert_eq!(invalid.byte_index(location, encoding), Ok(idx));
+    }
+  }
+  assert_eq!(invalid.location(ByteIndex(2)).unwrap(), Location::new(0usize, 2usize));
+  assert_eq!(invalid.location_utf16(ByteIndex(7)).unwrap(), Location::new(1usize, 3usize));
+
+  // "falcon" is on the second line.
+  let falcon = source.location_span(
+    Location::new(1usize, 10usize),
+    Location::new(1usize, 4usize),
+    PositionEncoding::Utf32
+  ).unwrap();
+  assert_eq!(falcon.fragment(), "falcon");
+
+  // The end of a line without a trailing newline is a valid position.
+  assert_eq!(accented.byte_index(Location::new(1usize, 4usize), PositionEncoding::Utf32), Ok(ByteIndex(12)));
+  assert!(matches!(
+    accented.byte_index(Location::new(0usize, 3usize), PositionEncoding::Utf8),
+    Err(LocationError::InvalidCharBoundary { given: ByteIndex(2) })
+  ));
+  assert!(matches!(
+    accented.byte_index(Location::new(0usize, 6usize), PositionEncoding::Utf32),
+    Err(LocationError::ColumnOutOfBounds { given: ColumnIndex(6), max: ColumnIndex(5) })
+  ));
+  assert!(matches!(
+    accented.byte_index(Location::new(2usize, 0usize), PositionEncoding::Utf32),
+    Err(LocationError::LineOutOfBounds { given: LineIndex(2), max: LineIndex(1) })
+  ));
+}
+
+#[test]
+fn tab_stop_aware_visual_columns() {
+  let mut source = Source::new("tabs", "\tif x {\n\t\ty\t= 1;\n}\n");
+  let y  = ByteIndex(10);
+  let eq = ByteIndex(12);
+
+  assert_eq!(source.tab_width(), 4);
+  assert_eq!(source.location(y).unwrap().column_index, ColumnIndex(2));
+  assert_eq!(source.visual_column(ByteIndex(1)), Ok(ColumnIndex(4)));
+  assert_eq!(source.visual_column(y), Ok(ColumnIndex(8)));
+  // "y" ends at column 9, and the tab after it advances to the next stop.
+  assert_eq!(source.visual_column(eq), Ok(ColumnIndex(12)));
+
+  source.set_tab_width(8);
+  assert_eq!(source.visual_column(y), Ok(ColumnIndex(16)));
+  assert_eq!(source.visual_column(eq), Ok(ColumnIndex(24)));
+
+  assert_eq!(source.byte_index_from_visual_column(LineIndex(1), ColumnIndex(16)), Ok(y));
+  assert_eq!(source.byte_index_from_visual_column(LineIndex(1), ColumnIndex(24)), Ok(eq));
+  assert!(matches!(
+    source.byte_index_from_visual_column(LineIndex(1), ColumnIndex(3)),
+    Err(LocationError::InvalidCharBoundary { given: ByteIndex(8) })
+  ));
+}
+
+#[test]
+fn line_endings_and_terminators() {
+  let crlf = Source::new("crlf", "one\r\ntwo\r\n");
+  assert_eq!(crlf.line_ending(), Some(LineEnding::CrLf));
+  assert_eq!(crlf.line_span(LineIndex(0)).unwrap().fragment(), "one\r\n");
+  assert_eq!(crlf.line_span_without_terminator(LineIndex(0)).unwrap().fragment(), "one");
+  assert_eq!(crlf.byte_index(Location::new(0usize, 3usize), PositionEncoding::Utf32), Ok(ByteIndex(3)));
+
+  let mut mac = Source::new("mac", "one\rtwo\u{2028}three");
+  assert_eq!(mac.line_ending(), Some(LineEnding::Cr));
+  assert_eq!(mac.last_line_index(), LineIndex(0));
+
+  mac.set_line_terminators(LineTerminators::All);
+  assert_eq!(mac.last_line_index(), LineIndex(2));
+  assert_eq!(mac.line_span_without_terminator(LineIndex(1)).unwrap().fragment(), "two");
+  assert_eq!(mac.line_span_without_terminator(LineIndex(2)).unwrap().fragment(), "three");
+  assert_eq!(mac.location(ByteIndex(4)).unwrap(), Location::new(1usize, 0usize));
+
+  // Joining a lone CR with an inserted LF makes a single CRLF terminator.
+  let mut mixed = Source::new("mixed", String::from("a\rb\nc"));
+  mixed.set_line_terminators(LineTerminators::All);
+  assert_eq!(mixed.line_ending(), Some(LineEnding::Mixed));
+  mixed.apply_edit(2..3, "\n").unwrap();
+  assert_eq!(mixed.text(), "a\r\n\nc");
+  assert_eq!(mixed.line_ending(), Some(LineEnding::Mixed));
+  mixed.apply_edit(3..4, "").unwrap();
+  assert_eq!(mixed.line_ending(), Some(LineEnding::CrLf));
+  assert_eq!(mixed.last_line_index(), LineIndex(1));
+  assert_eq!(mixed.line_start(LineIndex(1)), Ok(ByteIndex(3)));
+}
+
+#[test]
+fn byte_order_marks_and_transcoding() {
+  let file     = "\u{FEFF}one\ntwo".as_bytes();
+  let with_bom = Source::decode("bom", file, TextEncoding::Utf8).unwrap();
+  assert_eq!(with_bom.bom(), Some(ByteOrderMark::Utf8));
+  assert_eq!(with_bom.text(), "one\ntwo");
+  assert_eq!(with_bom.location(ByteIndex(1)).unwrap(), Location::new(0usize, 1usize));
+  assert_eq!(with_bom.original_offset(ByteIndex(4)), 7);
+  assert_eq!(with_bom.encoded_bytes().unwrap(), file);
+
+  // Text given to `Source::new` keeps its byte-order mark, so offsets are into the text as given.
+  let bytes = Source::new("bytes", file);
+  assert_eq!(bytes.bom(), None);
+  assert_eq!(bytes.text(), file);
+  assert_eq!(bytes.slice(3..6).fragment(), b"one");
+  assert_eq!(bytes.location(ByteIndex(7)).unwrap(), Location::new(1usize, 0usize));
+  assert_eq!(bytes.original_offset(ByteIndex(4)), 4);
+  let text = Source::new("text", "\u{FEFF}one\ntwo");
+  assert_eq!(text.text(), "\u{FEFF}one\ntwo");
+  assert_eq!(text.slice(3..6).fragment(), "one");
+  let fix = crate::Suggestion::new(text.slice(3..6), "1", crate::Applicability::MachineApplicable);
+  assert_eq!(text.apply_suggestions([&fix]).unwrap(), "\u{FEFF}1\ntwo");
+
+  // "a\u{e9}\u{1F600}\n" in UTF-16BE without a byte-order mark.
+  let utf16 = [0, b'a', 0, 0xE9, 0xD8, 0x3D, 0xDE, 0x00, 0, b'\n'];
+  let source = Source::decode("utf16", &utf16, TextEncoding::Utf16Be).unwrap();
+  assert_eq!(source.text(), "a\u{e9}\u{1F600}\n");
+  assert_eq!(source.bom(), None);
+  assert_eq!(source.text_encoding(), TextEncoding::Utf16Be);
+  let offsets: Vec<usize> =
+    [0, 1, 3, 7, 8].iter().map(|&i| source.original_offset(ByteIndex(i))).collect();
+  assert_eq!(offsets, vec![0, 2, 4, 8, 10]);
+  assert_eq!(source.encoded_bytes().unwrap(), utf16);
+
+  let latin1 = Source::decode("latin1", b"caf\xe9 ol\xe9", TextEncoding::Latin1).unwrap();
+  assert_eq!(latin1.text(), "caf\u{e9} ol\u{e9}");
+  assert_eq!(latin1.original_offset(ByteIndex(6)), 5);
+  assert_eq!(latin1.encoded_bytes().unwrap(), b"caf\xe9 ol\xe9");
+
+  let odd = Source::decode("odd", &[0xFF, 0xFE, b'a'], TextEncoding::Utf8);
+  assert_eq!(odd.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn multi_line_span_queries() {
+  let source = Source::new(SOURCE_NAME, SOURCE_TEXT);
+  // From "falconer;" on the second line through "Things fall" on the third.
+  let span = source.slice(68..89);
+
+  assert_eq!(span.location().unwrap(), Location::new(1usize, 27usize));
+  assert_eq!(span.end_location().unwrap(), Location::new(2usize, 11usize));
+  assert_eq!(span.line_range().unwrap(), LineIndex(1)..=LineIndex(2));
+
+  let pieces: Vec<_> = span.line_pieces().unwrap().collect();
+  assert_eq!(pieces.len(), 2);
+  assert_eq!(pieces[0].span.fragment(), "falconer;");
+  assert_eq!((pieces[0].start_column, pieces[0].end_column), (ColumnIndex(27), ColumnIndex(36)));
+  assert_eq!(pieces[1].line, LineIndex(2));
+  assert_eq!(pieces[1].span.fragment(), "Things fall");
+  assert_eq!((pieces[1].start_column, pieces[1].end_column), (ColumnIndex(0), ColumnIndex(11)));
+
+  // A span ending just after a newline doesn't touch the next line.
+  let first_line = source.line_span(LineIndex(0)).unwrap();
+  assert_eq!(first_line.line_range().unwrap(), LineIndex(0)..=LineIndex(0));
+  assert_eq!(first_line.line_pieces().unwrap().count(), 1);
+
+  // The end of a text without a trailing newline has a location.
+  let whole = source.source_span();
+  assert_eq!(whole.end_location().unwrap(), Location::new(7usize, 33usize));
+  assert_eq!(whole.line_pieces().unwrap().count(), 8);
+
+  let mut kana = Source::new("Japanese kana", "メカ\nジキ");
+  kana.set_position_encoding(PositionEncoding::Utf8);
+  let pieces: Vec<_> = kana.slice(3..13).line_pieces().unwrap().collect();
+  assert_eq!((pieces[0].start_column, pieces[0].end_column), (ColumnIndex(3), ColumnIndex(6)));
+  assert_eq!((pieces[1].start_column, pieces[1].end_column), (ColumnIndex(0), ColumnIndex(6)));
+  let pieces: Vec<_> = kana.slice(3..13).line_pieces_in(PositionEncoding::Utf32).unwrap().collect();
+  assert_eq!((pieces[0].start_column, pieces[0].end_column), (ColumnIndex(1), ColumnIndex(2)));
+  assert_eq!((pieces[1].start_column, pieces[1].end_column), (ColumnIndex(0), ColumnIndex(2)));
+}
+
+#[test]
+fn line_and_char_iterators() {
+  let source = Source::new("lines", "one\r\ntwo\n\nfour\n");
+  let lines: Vec<_> = source.lines().map(|(line, span)| (line.0, span.fragment())).collect();
+  assert_eq!(lines, vec![(0, "one"), (1, "two"), (2, ""), (3, "four")]);
+
+  // "ne\r\ntwo\n\nfo"
+  let span  = source.slice(1..12);
+  let lines: Vec<_> = span.lines().map(|(_, span)| span.fragment()).collect();
+  assert_eq!(lines, vec!["ne", "two", "", "fo"]);
+  assert_eq!(Source::new("empty", "").lines().count(), 1);
+
+  let kana  = Source::new("Japanese kana", "メカ\nジ");
+  let chars: Vec<_> = kana.source_span().char_indices().collect();
+  assert_eq!(
+    chars,
+    vec![(ByteIndex(0), 'メ'), (ByteIndex(3), 'カ'), (ByteIndex(6), '\n'), (ByteIndex(7), 'ジ')]
+  );
+  let spans: Vec<_> = kana.slice(3..10).char_spans().map(|span| span.start()).collect();
+  assert_eq!(spans, vec![ByteIndex(3), ByteIndex(6), ByteIndex(7)]);
+
+  let bytes = Source::new("bytes", b"a\xffb".as_slice());
+  let chars: Vec<_> = bytes.source_span().char_indices().map(|(_, c)| c).collect();
+  assert_eq!(chars, vec!['a', char::REPLACEMENT_CHARACTER, 'b']);
+  assert_eq!(bytes.source_span().char_spans().nth(1).unwrap().fragment(), b"\xff");
+}
+
+#[test]
+fn span_set_algebra() {
+  let source = Source::new("algebra", "0123456789");
+  let other  = Source::new("other", "0123456789");
+  let a = source.slice(2..6);
+  let b = source.slice(4..8);
+  let c = source.slice(6..9);
+
+  assert_eq!(a.intersect(b).unwrap().unwrap().fragment(), "45");
+  assert!(a.intersect(c).unwrap().unwrap().is_empty());
+  assert!(a.intersect(source.slice(7..9)).unwrap().is_none());
+
+  let (before, after) = source.slice(1..9).subtract(b).unwrap();
+  assert_eq!((before.unwrap().fragment(), after.unwrap().fragment()), ("123", "8"));
+  let (before, after) = a.subtract(b).unwrap();
+  assert_eq!((before.unwrap().fragment(), after), ("23", None));
+  assert_eq!(b.subtract(source.slice(0..10)).unwrap(), (None, None));
+
+  assert!(source.slice(1..9).contains(b).unwrap());
+  assert!(!a.contains(b).unwrap());
+  assert!(a.contains_index(ByteIndex(5)));
+  assert!(!a.contains_index(ByteIndex(6)));
+
+  assert!(a.is_adjacent(c).unwrap());
+  assert!(c.is_adjacent(a).unwrap());
+  assert!(!a.is_adjacent(b).unwrap());
+
+  assert_eq!(source.slice(0..2).gap_between(c).unwrap().unwrap().fragment(), "2345");
+  assert!(a.gap_between(c).unwrap().unwrap().is_empty());
+  assert!(a.gap_between(b).unwrap().is_none());
+
+  assert_eq!(a.shrink_to_lo().start(), ByteIndex(2));
+  assert_eq!(a.shrink_to_hi().start(), ByteIndex(6));
+  assert!(a.shrink_to_hi().is_empty());
+
+  let foreign = other.slice(2..6);
+  assert!(a.intersect(foreign).is_err());
+  assert!(a.subtract(foreign).is_err());
+  assert!(a.contains(foreign).is_err());
+  assert!(a.is_adjacent(foreign).is_err());
+  assert!(a.gap_between(foreign).is_err());
+}
+
+#[test]
+fn span_tree_queries() {
+  let (main, other) = (SourceId(0), SourceId(1));
+  // `fn f() { g(x) }`
+  let mut tree: SpanTree<&str> = [
+    (IdSpan::new(0usize, 15usize, main), "item"),
+    (IdSpan::new(7usize, 8usize, main), "block"),
+    (IdSpan::new(9usize, 4usize, main), "call"),
+    (IdSpan::new(11usize, 1usize, main), "arg"),
+    (IdSpan::new(0usize, 15usize, other), "other"),
+  ].into_iter().collect();
+  tree.insert(IdSpan::new(11usize, 1usize, main), "path");
+  assert_eq!(tree.len(), 6);
+
+  let names = |query: SpanTreeQuery<&'static str>| query.map(|(_, name)| *name).collect::<Vec<_>>();
+  assert_eq!(names(tree.at(main, ByteIndex(11))), ["item", "block", "call", "arg", "path"]);
+  assert_eq!(names(tree.at(main, ByteIndex(15))), Vec::<&str>::new());
+  assert_eq!(names(tree.overlapping(IdSpan::new(12usize, 2usize, main))), ["item", "block", "call"]);
+  assert_eq!(names(tree.overlapping(IdSpan::new(3usize, 0usize, main))), ["item"]);
+  assert_eq!(names(tree.containing(IdSpan::new(12usize, 0usize, main))), ["item", "block", "call", "arg", "path"]);
+  assert_eq!(tree.innermost_at(main, ByteIndex(11)).map(|(_, name)| *name), Some("path"));
+  assert_eq!(tree.innermost_at(main, ByteIndex(10)).map(|(_, name)| *name), Some("call"));
+  assert_eq!(tree.innermost_containing(IdSpan::new(7usize, 8usize, main)).unwrap().1, &"block");
+  assert_eq!(names(tree.iter_source(other)), ["other"]);
+  assert!(tree.innermost_at(SourceId(2), ByteIndex(0)).is_none());
+
+  // Queries at the largest index do not overflow.
+  let last = ByteIndex(RawIndex::MAX);
+  tree.insert(IdSpan::new(RawIndex::MAX as usize - 1, 1usize, main), "last");
+  assert_eq!(names(tree.at(main, ByteIndex(RawIndex::MAX - 1))), ["last"]);
+  assert_eq!(names(tree.at(main, last)), Vec::<&str>::new());
+  assert_eq!(names(tree.overlapping(IdSpan::new(last, 0usize, main))), Vec::<&str>::new());
+  assert_eq!(names(tree.containing(IdSpan::new(last, 0usize, main))), ["last"]);
+
+  // Compare against a linear scan on a larger tree built in a scrambled order.
+  let spans: Vec<IdSpan> =
+      (0..500u32).map(|i| {
+        let start = (i * 7919) % 1000;
+        IdSpan::new(start as usize, ((i * 104729) % 37) as usize, main)
+      }).collect();
+  let tree: SpanTree<usize> = spans.iter().copied().zip(0..).collect();
+  for index in (0..1040).step_by(3).map(ByteIndex) {
+    let mut expected: Vec<usize> =
+        (0..spans.len()).filter(|&i| spans[i].start() <= index && index < spans[i].end()).collect();
+    let mut found: Vec<usize> = tree.at(main, index).map(|(_, &i)| i).collect();
+    expected.sort();
+    found.sort();
+    assert_eq!(found, expected);
+  }
+}
+
+#[test]
+fn compact_spans_round_trip() {
+  let mut sources = Sources::new();
+  let first  = sources.add("first", "fn main() {}\n");
+  let second = sources.add("second", "let x = 1;\n");
+
+  let token   = sources.get(second).unwrap().slice(4..5);
+  let compact = token.compact(&sources).unwrap();
+  assert!(compact.is_inline());
+  assert_eq!(compact.span(&sources).unwrap(), token);
+  assert_eq!(compact, sources.compact(token.id_span().unwrap()));
+
+  // Spans too long or with too large a `SourceId` are interned once.
+  let long      = IdSpan::new(0usize, 70_000usize, first);
+  let far       = IdSpan::new(3usize, 2usize, SourceId(70_000));
+  let interned  = sources.compact(long);
+  assert!(!interned.is_inline());
+  assert!(CompactSpan::inline(far).is_none());
+  assert_eq!(interned.id_span(&sources), long);
+  assert_eq!(sources.compact(far).id_span(&sources), far);
+  assert_eq!(sources.compact(long), interned);
+  assert_ne!(sources.compact(far), interned);
+
+  // A clone of the database can expand the spans interned before it was cloned.
+  let copy = sources.clone();
+  assert_eq!(interned.id_span(&copy), long);
+  assert!(sources.compact(far).span(&sources).is_err());
+
+  // Only inline compact spans are meaningful without their database, so only they serialize.
+  #[cfg(feature = "serialization")]
+  {
+    let json = serde_json::to_value(compact).unwrap();
+    assert_eq!(serde_json::from_value::<CompactSpan>(json).unwrap(), compact);
+    assert!(serde_json::to_value(interned).is_err());
+    let forged = serde_json::json!({ "start_or_index": 0, "length_or_tag": u16::MAX, "source": 0 });
+    assert!(serde_json::from_value::<CompactSpan>(forged).is_err());
+  }
+}
+
+#[test]
+fn checked_index_construction() {
+  let source = Source::new("short", "abc");
+  assert_eq!(ByteIndex::try_new(3), Ok(ByteIndex(3)));
+  assert_eq!(Span::try_new(1, 2, &source).unwrap().fragment(), "bc");
+
+  #[cfg(not(feature = "u64-indices"))]
+  {
+    let too_large = crate::RawIndex::MAX as usize + 1;
+    assert_eq!(ByteIndex::try_new(too_large), Err(crate::IndexOverflowError { given: too_large }));
+    assert!(LineIndex::try_new(too_large).is_err());
+    assert!(SourceId::try_new(too_large).is_err());
+    assert!(Span::try_new(too_large - 1, 1, &source).is_err());
+    assert!(Span::try_new(too_large - 1, 0, &source).is_ok());
+    // Conversions that used to truncate now panic instead.
+    assert!(std::panic::catch_unwind(|| ByteIndex::from(too_large)).is_err());
+    assert!(std::panic::catch_unwind(|| SourceId::new(too_large)).is_err());
+  }
+  #[cfg(feature = "u64-indices")]
+  assert_eq!(ByteIndex::try_new(1 << 32), Ok(ByteIndex(1 << 32)));
+}
+
+#[cfg(feature = "reporting")]
+#[test]
+fn emit_diagnostics_built_from_spans() {
+  use codespan_reporting::term::{self, termcolor::NoColor};
+  use crate::{Diagnostic, Label, Severity};
+
+  let mut sources = Sources::new();
+  let main = sources.add("main.x", "use util;\nlet y = util::f(1, 2);\n");
+  let util = sources.add("util.x", "fn f(a) {\n  a\n}\n");
+
+  let call       = sources.get(main).unwrap().slice(18..31);
+  let definition = sources.get(util).unwrap().slice(0..7);
+  let diagnostic =
+      Diagnostic::error()
+          .with_code("E0061")
+          .with_message("wrong number of arguments")
+          .with_labels([
+            Label::primary(call).with_message("two arguments given"),
+            Label::secondary(definition).with_message("one parameter declared here"),
+          ])
+          .with_note("remove the extra argument");
+  assert_eq!(diagnostic.severity, Severity::Error);
+  assert!(Severity::Error > Severity::Warning);
+
+  let config     = term::Config::default();
+  let mut output = NoColor::new(Vec::new());
+  diagnostic.emit_sources(&mut output, &config, &sources).unwrap();
+  let output = String::from_utf8(output.into_inner()).unwrap();
+  assert!(output.starts_with("error[E0061]: wrong number of arguments\n"));
+  assert!(output.contains("main.x:2:9"));
+  assert!(output.contains("util.x:1:1"));
+  assert!(output.contains("two arguments given"));
+  assert!(output.contains("= remove the extra argument"));
+
+  // Emitting against a single source requires every label to be in that source.
+  let mut output = NoColor::new(Vec::new());
+  let source     = sources.get(main).unwrap();
+  assert!(diagnostic.emit(&mut output, &config, source).is_err());
+  let local = Diagnostic::warning().with_label(Label::primary(call));
+  assert!(local.emit(&mut output, &config, source).is_ok());
+
+  // A span of a source outside the database cannot be reported against it.
+  let loose = Source::new("loose.x", "");
+  let loose = Diagnostic::error().with_label(Label::primary(loose.source_span()));
+  assert!(loose.to_codespan_sources(&sources).is_err());
+
+  // Byte sources are only reported if they are valid UTF-8, as codespan needs the text as a `str`
+  // with the same byte offsets.
+  let valid   = Source::new("valid.x", &b"let x = \xC3\xA9\n"[..]);
+  let invalid = Source::new("invalid.x", &b"\xff\xff\xff x = \xe9\n"[..]);
+  let mut output = NoColor::new(Vec::new());
+  let diagnostic = Diagnostic::error().with_label(Label::primary(valid.slice(8..10)));
+  assert!(diagnostic.emit(&mut output, &config, &valid).is_ok());
+  assert!(String::from_utf8(output.into_inner()).unwrap().contains("let x = \u{e9}"));
+  let diagnostic = Diagnostic::error().with_label(Label::primary(invalid.slice(8..9)));
+  assert!(diagnostic.emit(&mut NoColor::new(Vec::new()), &config, &invalid).is_err());
+  assert!(codespan_reporting::files::Files::source(&invalid, ()).is_err());
+}
+
+#[test]
+fn render_diagnostics_natively() {
+  use crate::{Diagnostic, Label, RenderConfig};
+
+  let source = Source::new("main.x", "fn main() {\n\tlet x = f(\n    a);\n}\n");
+  let diagnostic =
+      Diagnostic::warning()
+          .with_code("W1")
+          .with_message("unused result")
+          .with_labels([
+            Label::primary(source.slice(21..31)).with_message("this call"),
+            Label::secondary(source.slice(13..16)),
+            Label::secondary(source.slice(0..33)).with_message("in this function"),
+          ])
+          .with_note("assign it to `_`\nto silence this warning");
+
+  // The tab is expanded to the next tab stop, and nested multi-line labels get their own columns.
+  assert_eq!(
+    diagnostic.to_string(),
+    concat!(
+      "warning[W1]: unused result\n",
+      " --> main.x:2:10\n",
+      "  |\n",
+      "1 |    fn main() {\n",
+      "  |  __-\n",
+      "2 | |      let x = f(\n",
+      "  | |      ---\n",
+      "  | | _____________^\n",
+      "3 | ||     a);\n",
+      "  | ||_______^ this call\n",
+      "4 | |  }\n",
+      "  | |__- in this function\n",
+      "  |\n",
+      "  = assign it to `_`\n",
+      "    to silence this warning\n",
+    )
+  );
+
+  let colored = diagnostic.render(&RenderConfig::colored());
+  assert!(colored.starts_with("\x1b[1;33mwarning[W1]\x1b[0m"));
+  assert!(colored.contains("\x1b[1;34m  |\x1b[0m \x1b[1;34m|\x1b[0m      \x1b[1;34m---\x1b[0m\n"));
+
+  // Labels in other sources are listed after the source of the primary label.
+  let other = Source::new("other.x", "x\n");
+  let diagnostic =
+      Diagnostic::error()
+          .with_label(Label::secondary(other.slice(0..1)))
+          .with_label(Label::primary(source.slice(36..36)));
+  assert_eq!(
+    diagnostic.to_string(),
+    concat!(
+      "error\n",
+      " --> main.x:5:1\n",
+      "  |\n",
+      "5 |\n",
+      "  | ^\n",
+      " ::: other.x:1:1\n",
+      "  |\n",
+      "1 | x\n",
+      "  | -\n",
+    )
+  );
+}
+
+#[test]
+fn suggestions_render_and_apply() {
+  use crate::{Applicability, Diagnostic, Label, Suggestion, SuggestionError};
+
+  let source = Source::new("main.x", "let x = 1\nlet y = x + z;;\n");
+  let other  = Source::new("other.x", "let x = 1\n");
+  let rename    = Suggestion::new(source.slice(22..23), "x", Applicability::MaybeIncorrect)
+                      .with_message("a local variable with a similar name exists");
+  let semicolon = Suggestion::new(source.slice(9..9), ";", Applicability::MachineApplicable)
+                      .with_message("add a semicolon");
+  let extra     = Suggestion::new(source.slice(24..25), "", Applicability::MachineApplicable)
+                      .with_message("remove the extra semicolon");
+
+  // Suggestions are applied in span order, whatever order they are given in.
+  assert_eq!(
+    source.apply_suggestions([&rename, &extra, &semicolon]).unwrap(),
+    "let x = 1;\nlet y = x + x;\n"
+  );
+  assert_eq!(
+    source.apply_suggestions([&semicolon, &semicolon.clone()]).unwrap(),
+    "let x = 1;\nlet y = x + z;;\n"
+  );
+  assert_eq!(source.apply_suggestions([]).unwrap(), source.text());
+
+  // Touching suggestions are fine, overlapping ones and competing insertions are not.
+  let touching = Suggestion::new(source.slice(23..24), "", Applicability::MachineApplicable);
+  assert_eq!(source.apply_suggestions([&rename, &touching]).unwrap(), "let x = 1\nlet y = x + x;\n");
+  let overlapping = Suggestion::new(source.slice(22..24), "x;", Applicability::MachineApplicable);
+  assert_eq!(
+    source.apply_suggestions([&overlapping, &rename]),
+    Err(SuggestionError::Overlapping { first: rename.span, second: overlapping.span })
+  );
+  let colon = Suggestion::new(source.slice(9..9), ":", Applicability::MachineApplicable);
+  assert!(
+    matches!(source.apply_suggestions([&semicolon, &colon]), Err(SuggestionError::Overlapping { .. }))
+  );
+  let foreign = Suggestion::new(other.slice(9..9), ";", Applicability::MachineApplicable);
+  assert_eq!(
+    source.apply_suggestions([&semicolon, &foreign]),
+    Err(SuggestionError::WrongSource { given: foreign.span })
+  );
+
+  // Spans within a character or past the end of the text are errors rather than panics.
+  let accent = Source::new("accent", "caf\u{e9}\n");
+  for span in [Span::new(4usize, 1usize, &accent), Span::new(9usize, 0usize, &accent)] {
+    let suggestion = Suggestion::new(span, "e", Applicability::MachineApplicable);
+    assert_eq!(accent.apply_suggestions([&suggestion]), Err(SuggestionError::InvalidSpan { given: span }));
+  }
+  // Invalid UTF-8 in a byte source is only an error if it is kept.
+  let bytes  = Source::new("bytes", &b"a\xFFb"[..]);
+  let keep   = Suggestion::new(bytes.slice(0..1), "x", Applicability::MachineApplicable);
+  let remove = Suggestion::new(bytes.slice(1..2), "y", Applicability::MachineApplicable);
+  assert_eq!(bytes.apply_suggestions([&keep]), Err(SuggestionError::InvalidUtf8 { given: ByteIndex(1) }));
+  assert_eq!(bytes.apply_suggestions([&remove]).unwrap(), "ayb");
+
+  let diagnostic =
+      Diagnostic::error()
+          .with_message("cannot find value `z`")
+          .with_label(Label::primary(source.slice(22..23)).with_message("not found"))
+          .with_suggestions([rename, semicolon, extra]);
+  assert_eq!(
+    diagnostic.to_string(),
+    concat!(
+      "error: cannot find value `z`\n",
+      " --> main.x:2:13\n",
+      "  |\n",
+      "2 | let y = x + z;;\n",
+      "  |             ^ not found\n",
+      "help: a local variable with a similar name exists\n",
+      "  |\n",
+      "2 | let y = x + x;;\n",
+      "  |             ~\n",
+      "help: add a semicolon\n",
+      "  |\n",
+      "1 | let x = 1;\n",
+      "  |          +\n",
+      "help: remove the extra semicolon\n",
+      "  |\n",
+      "2 - let y = x + z;;\n",
+      "2 + let y = x + z;\n",
+    )
+  );
+  #[cfg(feature = "reporting")]
+  assert_eq!(
+    diagnostic.to_codespan(&source).unwrap().notes,
+    [
+      "help: a local variable with a similar name exists: `x`",
+      "help: add a semicolon: `;`",
+      "help: remove the extra semicolon: remove `;`",
+    ]
+  );
+
+  // Removing a whole line only shows the removed line.
+  let diagnostic =
+      Diagnostic::warning()
+          .with_message("unused variable")
+          .with_suggestion(Suggestion::new(source.slice(0..10), "", Applicability::MaybeIncorrect));
+  assert_eq!(
+    diagnostic.to_string(),
+    concat!(
+      "warning: unused variable\n",
+      "help\n",
+      " --> main.x:1:1\n",
+      "  |\n",
+      "1 - let x = 1\n",
+    )
+  );
+
+  // The gutter is as wide as the largest line number shown, here 9.
+  let nine = Source::new("nine.x", "1\n2\n3\n4\n5\n6\n7\n8\nlet x = 9\n");
+  let diagnostic =
+      Diagnostic::error()
+          .with_suggestion(Suggestion::new(nine.slice(25..25), ";", Applicability::MachineApplicable));
+  assert_eq!(
+    diagnostic.to_string(),
+    concat!(
+      "error\n",
+      "help\n",
+      " --> nine.x:9:10\n",
+      "  |\n",
+      "9 | let x = 9;\n",
+      "  |          +\n",
+    )
+  );
+}
+
+#[cfg(feature = "serialization")]
+#[test]
+fn serialize_diagnostics_as_json_and_sarif() {
+  use crate::{Applicability, Diagnostic, JsonDiagnostic, Label, SarifLog, Suggestion};
+
+  let mut sources = Sources::new();
+  let main  = sources.add("main.x", "let s = \"😀\" + 1;\n");
+  let other = sources.add("other.x", "s\n");
+  let call  = sources.get(main).unwrap().slice(4..5).id_span().unwrap();
+  let macro_source =
+      sources.add_expansion("<m>", "s +\n s", Expansion { call_site: call, definition: call }).unwrap();
+  // JSON columns count characters whatever the position encoding of the source.
+  sources.get_mut(main).unwrap().set_position_encoding(PositionEncoding::Utf16);
+  let main  = sources.get(main).unwrap();
+  let other = sources.get(other).unwrap();
+
+  let mismatch =
+      Diagnostic::error()
+          .with_code("E0308")
+          .with_message("mismatched types")
+          .with_label(Label::primary(main.slice(17..18)).with_message("expected string"))
+          .with_label(Label::secondary(main.slice(4..5)))
+          .with_note("strings and numbers do not mix")
+          .with_suggestion(
+            Suggestion::new(main.slice(17..18), "\"1\"", Applicability::MaybeIncorrect)
+                .with_message("use a string")
+          );
+  let unused =
+      Diagnostic::warning()
+          .with_code("E0308")
+          .with_message("unused variable")
+          .with_label(Label::primary(other.slice(0..1)));
+
+  // rustc's JSON format, with columns in characters.
+  let json = serde_json::to_value(JsonDiagnostic::new(&mismatch)).unwrap();
+  assert_eq!(json["$message_type"], "diagnostic");
+  assert_eq!(json["level"], "error");
+  assert_eq!(json["code"]["code"], "E0308");
+  assert_eq!(json["spans"][0]["file_name"], "main.x");
+  assert_eq!(json["spans"][0]["byte_start"], 17);
+  assert_eq!(json["spans"][0]["byte_end"], 18);
+  assert_eq!(json["spans"][0]["line_start"], 1);
+  assert_eq!(json["spans"][0]["column_start"], 15);
+  assert_eq!(json["spans"][0]["column_end"], 16);
+  assert_eq!(json["spans"][0]["is_primary"], true);
+  assert_eq!(json["spans"][0]["label"], "expected string");
+  assert_eq!(
+    json["spans"][0]["text"],
+    serde_json::json!([{ "text": "let s = \"😀\" + 1;", "highlight_start": 15, "highlight_end": 16 }])
+  );
+  assert!(json["spans"][0]["expansion"].is_null());
+  assert_eq!(json["spans"][1]["is_primary"], false);
+  assert!(json["spans"][1]["label"].is_null());
+  assert_eq!(json["children"][0]["level"], "note");
+  assert_eq!(json["children"][0]["message"], "strings and numbers do not mix");
+  assert_eq!(json["children"][1]["level"], "help");
+  assert_eq!(json["children"][1]["message"], "use a string");
+  assert_eq!(json["children"][1]["spans"][0]["byte_start"], 17);
+  assert_eq!(json["children"][1]["spans"][0]["suggested_replacement"], "\"1\"");
+  assert_eq!(json["children"][1]["spans"][0]["suggestion_applicability"], "MaybeIncorrect");
+  assert!(json["spans"][0]["suggested_replacement"].is_null());
+  assert_eq!(json["rendered"], mismatch.to_string());
+  let round_trip: JsonDiagnostic = serde_json::from_value(json).unwrap();
+  assert_eq!(round_trip, JsonDiagnostic::new(&mismatch));
+
+  // Spans in synthetic sources carry the expansion that produced them.
+  let expanded =
+      Diagnostic::error()
+          .with_label(Label::primary(sources.get(macro_source).unwrap().slice(2..6)));
+  let json = serde_json::to_value(JsonDiagnostic::with_expansions(&expanded, &sources)).unwrap();
+  let span = &json["spans"][0];
+  assert_eq!(
+    span["text"],
+    serde_json::json!([
+      { "text": "s +", "highlight_start": 3, "highlight_end": 4 },
+      { "text": " s", "highlight_start": 1, "highlight_end": 3 },
+    ])
+  );
+  assert_eq!(span["expansion"]["macro_decl_name"], "<m>");
+  assert_eq!(span["expansion"]["span"]["file_name"], "main.x");
+  assert_eq!(span["expansion"]["span"]["column_start"], 5);
+  assert_eq!(span["expansion"]["span"]["is_primary"], false);
+  assert!(span["expansion"]["span"]["expansion"].is_null());
+  assert_eq!(span["expansion"]["def_site_span"]["byte_start"], 4);
+  assert!(serde_json::to_value(JsonDiagnostic::new(&expanded)).unwrap()["spans"][0]["expansion"].is_null());
+
+  // SARIF, with columns in UTF-16 code units.
+  let sarif = serde_json::to_value(SarifLog::new("checker", [&mismatch, &unused])).unwrap();
+  assert_eq!(sarif["version"], "2.1.0");
+  let run = &sarif["runs"][0];
+  assert_eq!(run["tool"]["driver"]["name"], "checker");
+  assert_eq!(run["tool"]["driver"]["rules"].as_array().unwrap().len(), 1);
+  assert_eq!(run["columnKind"], "utf16CodeUnits");
+
+  let result = &run["results"][0];
+  assert_eq!(result["ruleId"], "E0308");
+  assert_eq!(result["level"], "error");
+  assert_eq!(result["message"]["text"], "mismatched types\nstrings and numbers do not mix");
+  let location = &result["locations"][0]["physicalLocation"];
+  assert_eq!(location["artifactLocation"]["uri"], "main.x");
+  assert_eq!(
+    location["region"],
+    serde_json::json!({
+      "startLine": 1, "startColumn": 16, "endLine": 1, "endColumn": 17,
+      "byteOffset": 17, "byteLength": 1
+    })
+  );
+  assert_eq!(result["relatedLocations"][0]["physicalLocation"]["region"]["startColumn"], 5);
+  assert!(result["relatedLocations"][0].get("message").is_none());
+  let fix = &result["fixes"][0];
+  assert_eq!(fix["description"]["text"], "use a string");
+  assert_eq!(fix["artifactChanges"][0]["artifactLocation"]["uri"], "main.x");
+  let replacement = &fix["artifactChanges"][0]["replacements"][0];
+  assert_eq!(replacement["deletedRegion"], location["region"]);
+  assert_eq!(replacement["insertedContent"]["text"], "\"1\"");
+
+  let result = &run["results"][1];
+  assert_eq!(result["level"], "warning");
+  assert_eq!(result["locations"][0]["physicalLocation"]["artifactLocation"]["uri"], "other.x");
+  assert!(result.get("relatedLocations").is_none());
+  assert!(result.get("fixes").is_none());
+
+  // Source names become URI references.
+  use crate::report::artifact_uri;
+  assert_eq!(artifact_uri("src/main.x"), "src/main.x");
+  assert_eq!(artifact_uri("my dir/a#1%.x"), "my%20dir/a%231%25.x");
+  assert_eq!(artifact_uri("h\u{e9}.x"), "h%C3%A9.x");
+  assert_eq!(artifact_uri("/home/me/main.x"), "file:///home/me/main.x");
+  assert_eq!(artifact_uri("C:\\src\\main.x"), "file:///C:/src/main.x");
+  assert_eq!(artifact_uri("\\\\server\\share\\main.x"), "file://server/share/main.x");
+
+  // Byte offsets are into the file as it is on disk, here "ab" in UTF-16LE with a byte-order mark.
+  let decoded = Source::decode("utf16.x", &[0xFF, 0xFE, b'a', 0, b'b', 0], TextEncoding::Utf8).unwrap();
+  let second  = Diagnostic::error().with_label(Label::primary(decoded.slice(1..2)));
+  let region  = &SarifLog::new("checker", [&second]).runs[0].results[0].locations[0].physical_location.region;
+  assert_eq!((region.byte_offset, region.byte_length), (4, 2));
+}
+
+#[cfg(feature = "reporting")]
+#[test]
+fn files_for_source_and_sources() {
+  use codespan_reporting::{
+    diagnostic::{Diagnostic, Label},
+    files::{Error as FileError, Files},
+    term::{self, termcolor::NoColor},
+  };
+
+  let mut sources = Sources::new();
+  let main = sources.add("main.x", "let x = 1;\nlet é = 2;\n");
+  let util = sources.add("util.x", "fn f()");
+  let missing = SourceId(7);
+
+  assert_eq!(sources.name(main).unwrap(), "main.x");
+  assert_eq!(Files::source(&sources, util).unwrap(), "fn f()");
+  assert!(matches!(Files::name(&sources, missing), Err(FileError::FileMissing)));
+  assert!(matches!(Files::line_range(&sources, missing, 0), Err(FileError::FileMissing)));
+
+  assert_eq!(Files::line_index(&sources, main, 15).unwrap(), 1);
+  assert_eq!(Files::line_index(&sources, main, 23).unwrap(), 2);
+  assert!(matches!(
+    Files::line_index(&sources, main, 24),
+    Err(FileError::IndexTooLarge { given: 24, max: 23 })
+  ));
+
+  // Line ranges include terminators, and the last line ends at the end of the text.
+  assert_eq!(Files::line_range(&sources, main, 0).unwrap(), 0..11);
+  assert_eq!(Files::line_range(&sources, main, 1).unwrap(), 11..23);
+  assert_eq!(Files::line_range(&sources, main, 2).unwrap(), 23..23);
+  assert_eq!(Files::line_range(&sources, util, 0).unwrap(), 0..6);
+  assert!(matches!(
+    Files::line_range(&sources, main, 3),
+    Err(FileError::LineTooLarge { given: 3, max: 2 })
+  ));
+
+  assert_eq!(Files::line_number(&sources, main, 1).unwrap(), 2);
+  assert!(Files::line_number(&sources, util, 1).is_err());
+
+  // Columns agree with `Source::location`.
+  assert_eq!(Files::column_number(&sources, main, 1, 17).unwrap(), 6);
+  assert!(matches!(
+    Files::column_number(&sources, main, 1, 16),
+    Err(FileError::InvalidCharBoundary { given: 16 })
+  ));
+  assert_eq!(Files::column_number(&sources, main, 0, 100).unwrap(), 12);
+  let location = Files::location(&sources, main, 17).unwrap();
+  let expected = sources.get(main).unwrap().location(ByteIndex(17)).unwrap();
+  assert_eq!((location.line_number, location.column_number), (2, 6));
+  assert_eq!(
+    (location.line_number, location.column_number),
+    (expected.line_number().0 as usize, expected.column_number().0 as usize)
+  );
+
+  let mut bytes = Source::new("bytes.x", "é = 1");
+  bytes.set_position_encoding(PositionEncoding::Utf8);
+  assert_eq!(Files::column_number(&bytes, (), 0, 2).unwrap(), 3);
+
+  // A label on the last line of a source without a trailing newline is shown.
+  let diagnostic =
+      Diagnostic::error()
+          .with_message("unused function")
+          .with_labels(vec![
+            Label::primary(util, 3..4),
+            Label::secondary(main, 15..17).with_message("here"),
+          ]);
+  let mut output = NoColor::new(Vec::new());
+  term::emit(&mut output, &term::Config::default(), &sources, &diagnostic).unwrap();
+  let output = String::from_utf8(output.into_inner()).unwrap();
+  assert!(output.contains("util.x:1:4"));
+  assert!(output.contains("1 │ fn f()"));
+  assert!(output.contains("2 │ let é = 2;"));
+}
+
+#[cfg(feature = "unicode-columns")]
+#[test]
+fn grapheme_and_display_width_columns() {
+  // "e" + combining acute, a wide CJK character, a flag (two regional indicators), and a tab.
+  let text = "e\u{301}\u{4E16}\u{1F1EF}\u{1F1F5}\tx\n";
+  let x    = ByteIndex::new(text.find('x').unwrap());
+  let mut source = Source::new("wide", text);
+  let graphemes = PositionEncoding::Graphemes;
+  let display   = PositionEncoding::DisplayWidth { tab_width: 4 };
+
+  assert_eq!(source.location_utf8(x).unwrap().column_index, ColumnIndex(6));
+  assert_eq!(source.location_in(x, graphemes).unwrap().column_index, ColumnIndex(4));
+  // 1 + 2 + 2 = 5 cells before the tab, which advances to 8.
+  assert_eq!(source.location_in(x, display).unwrap().column_index, ColumnIndex(8));
+  assert_eq!(
+    source.location_in(x, PositionEncoding::DisplayWidth { tab_width: 8 }).unwrap().column_index,
+    ColumnIndex(8)
+  );
+
+  source.set_position_encoding(display);
+  assert_eq!(source.slice(usize::from(x)..).location().unwrap().column_index, ColumnIndex(8));
+
+  assert_eq!(source.byte_index(Location::new(0usize, 8usize), display), Ok(x));
+  assert_eq!(source.byte_index(Location::new(0usize, 1usize), graphemes), Ok(ByteIndex(3)));
+  // Column 2 is the second cell of the wide character.
+  assert!(matches!(
+    source.byte_index(Location::new(0usize, 2usize), display),
+    Err(LocationError::InvalidCharBoundary { given: ByteIndex(3) })
+  ));
+}
+
+#[cfg(feature = "nom-parsing")]
+#[test]
+fn parse_str_and_byte_spans() {
+  use nom::{
+    bytes::complete::{tag, take_while1},
+    IResult,
+  };
+
+  fn word<'n, 't>(input: Span<'n, 't>) -> IResult<Span<'n, 't>, Span<'n, 't>> {
+    take_while1(|c: char| c.is_alphabetic())(input)
+  }
+
+  fn magic<'n, 't>(input: Span<'n, 't, &'t [u8]>)
+    -> IResult<Span<'n, 't, &'t [u8]>, Span<'n, 't, &'t [u8]>>
+  {
+    tag(b"\x7fELF".as_slice())(input)
+  }
+
+  let source = Source::new(SOURCE_NAME, SOURCE_TEXT);
+  let (rest, turning) = word(source.source_span()).unwrap();
+  assert_eq!(turning.fragment(), "Turning");
+  assert_eq!(rest.start(), ByteIndex(7));
+
+  let binary = Source::new("a.out", b"\x7fELF\x02\x01".as_slice());
+  let (rest, elf) = magic(binary.source_span()).unwrap();
+  assert_eq!(elf.fragment(), b"\x7fELF");
+  assert_eq!(rest.fragment(), b"\x02\x01");
 }
 
 /*
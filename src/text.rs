@@ -0,0 +1,189 @@
+/*!
+
+The traits that make `Source` and `Span` generic over the type of the source text. A `Source` can be
+made from a string slice (`&str`) or a byte slice (`&[u8]`), or from an owned or shared
+counterpart such as `String`, `Rc<str>`, `Arc<str>`, `Cow<'_, str>`, or `Vec<u8>`. The
+`SourceText` trait describes the type a `Source` stores, while the `TextSlice` trait describes the
+(unsized) type a `Span`'s fragment is a slice of, namely `str` or `[u8]`.
+
+*/
+
+use std::{
+  borrow::Cow,
+  io,
+  ops::Range,
+  rc::Rc,
+  sync::Arc,
+};
+
+use crate::AsBytes;
+
+
+/// The unsized slice types a `Span` can refer to, namely `str` and `[u8]`. All positions are
+/// byte positions regardless of the slice type.
+pub trait TextSlice: AsBytes {
+  /// The length of the slice in bytes.
+  fn byte_len(&self) -> usize {
+    self.as_bytes().len()
+  }
+
+  /// Gives the subslice of `self` corresponding to the byte range `range`. Panics if `range` is
+  /// out of bounds or, in the case of `str`, does not lie on `char` boundaries.
+  fn subslice(&self, range: Range<usize>) -> &Self;
+
+  /// A (lossy) string representation of the slice for display purposes.
+  fn to_str_lossy(&self) -> Cow<'_, str>;
+
+  /// Whether `index` may be used as the start or end of a subslice. For `str` this is whether
+  /// `index` is on a `char` boundary. Indices past the end are never boundaries.
+  fn is_boundary(&self, index: usize) -> bool;
+}
+
+impl TextSlice for str {
+  #[inline(always)]
+  fn subslice(&self, range: Range<usize>) -> &Self {
+    &self[range]
+  }
+
+  #[inline(always)]
+  fn to_str_lossy(&self) -> Cow<'_, str> {
+    Cow::Borrowed(self)
+  }
+
+  #[inline(always)]
+  fn is_boundary(&self, index: usize) -> bool {
+    self.is_char_boundary(index)
+  }
+}
+
+impl TextSlice for [u8] {
+  #[inline(always)]
+  fn subslice(&self, range: Range<usize>) -> &Self {
+    &self[range]
+  }
+
+  #[inline(always)]
+  fn to_str_lossy(&self) -> Cow<'_, str> {
+    String::from_utf8_lossy(self)
+  }
+
+  #[inline(always)]
+  fn is_boundary(&self, index: usize) -> bool {
+    index <= self.len()
+  }
+}
+
+
+/// The types a `Source` can store its text as. Borrowed slices (`&str`, `&[u8]`) leave ownership of
+/// the text with the caller, `String` and `Vec<u8>` give ownership to the `Source`, and smart
+/// pointers share the text with the rest of the program.
+pub trait SourceText {
+  /// The type of slice `Span` fragments of this text are, either `str` or `[u8]`.
+  type Slice: ?Sized + TextSlice;
+
+  /// The entire text as a slice.
+  fn as_slice(&self) -> &Self::Slice;
+}
+
+impl SourceText for &str {
+  type Slice = str;
+
+  #[inline(always)]
+  fn as_slice(&self) -> &str {
+    self
+  }
+}
+
+impl SourceText for &[u8] {
+  type Slice = [u8];
+
+  #[inline(always)]
+  fn as_slice(&self) -> &[u8] {
+    self
+  }
+}
+
+
+/// Implements `SourceText` for owned and shared storage of the given slice type.
+macro_rules! impl_source_text {
+  ($Slice:ty; $($Storage:ty),+) => {
+    $(
+      impl SourceText for $Storage {
+        type Slice = $Slice;
+
+        #[inline(always)]
+        fn as_slice(&self) -> &$Slice {
+          self
+        }
+      }
+    )+
+  };
+}
+
+impl_source_text!(str; String, Box<str>, Rc<str>, Arc<str>, Cow<'_, str>);
+impl_source_text!([u8]; Vec<u8>, Box<[u8]>, Rc<[u8]>, Arc<[u8]>, Cow<'_, [u8]>);
+
+
+/// Source text types that can be created from the raw contents of a file. String types require
+/// the contents to be valid UTF-8. (See `Sources::load_file`.)
+pub trait FromFileContents: SourceText + Sized {
+  fn from_file_contents(contents: Vec<u8>) -> io::Result<Self>;
+}
+
+macro_rules! impl_from_file_contents {
+  (str; $($Storage:ty),+) => {
+    $(
+      impl FromFileContents for $Storage {
+        fn from_file_contents(contents: Vec<u8>) -> io::Result<Self> {
+          String::from_utf8(contents)
+              .map(Into::into)
+              .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        }
+      }
+    )+
+  };
+  ([u8]; $($Storage:ty),+) => {
+    $(
+      impl FromFileContents for $Storage {
+        fn from_file_contents(contents: Vec<u8>) -> io::Result<Self> {
+          Ok(contents.into())
+        }
+      }
+    )+
+  };
+}
+
+impl_from_file_contents!(str; String, Box<str>, Rc<str>, Arc<str>, Cow<'_, str>);
+impl_from_file_contents!([u8]; Vec<u8>, Box<[u8]>, Rc<[u8]>, Arc<[u8]>, Cow<'_, [u8]>);
+
+
+/// Source text types that can be modified in place. (See `Source::apply_edit`.)
+pub trait EditableText: SourceText {
+  /// Replaces the bytes in `range` with `replacement`. The caller guarantees that `range` is in
+  /// bounds and its ends are boundaries. (See `TextSlice::is_boundary`.)
+  fn replace_range(&mut self, range: Range<usize>, replacement: &Self::Slice);
+}
+
+impl EditableText for String {
+  fn replace_range(&mut self, range: Range<usize>, replacement: &str) {
+    String::replace_range(self, range, replacement);
+  }
+}
+
+impl EditableText for Cow<'_, str> {
+  fn replace_range(&mut self, range: Range<usize>, replacement: &str) {
+    self.to_mut().replace_range(range, replacement);
+  }
+}
+
+impl EditableText for Vec<u8> {
+  fn replace_range(&mut self, range: Range<usize>, replacement: &[u8]) {
+    self.splice(range, replacement.iter().copied());
+  }
+}
+
+impl EditableText for Cow<'_, [u8]> {
+  fn replace_range(&mut self, range: Range<usize>, replacement: &[u8]) {
+    self.to_mut().splice(range, replacement.iter().copied());
+  }
+}